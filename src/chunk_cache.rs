@@ -0,0 +1,475 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{crypto::CHUNK_OVERHEAD, error::FilenSDKError, FilenSDK, CHUNK_SIZE};
+
+type ChunkKey = (String, u64);
+
+/// On-disk cache of encrypted chunks, shared by every download of an SDK instance.
+///
+/// Chunks are stored as received from egest, in `<dir>/<file uuid>/<chunk index>`, so no
+/// plaintext ever touches the disk. Once the cached chunks exceed the size cap, the least
+/// recently used ones are evicted. Recency survives restarts through the files' modification
+/// times. The cache is disabled until `FilenSDK::enable_chunk_cache` is called.
+#[derive(Default)]
+pub struct EncryptedChunkCache {
+    state: Mutex<Option<CacheState>>,
+}
+
+struct CacheState {
+    dir: PathBuf,
+    max_bytes: u64,
+    total_bytes: u64,
+    /// Size and last use of every cached chunk
+    entries: HashMap<ChunkKey, (u64, u64)>,
+    /// Cached chunks by last use, oldest first
+    recency: BTreeMap<u64, ChunkKey>,
+    tick: u64,
+}
+
+impl CacheState {
+    fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            max_bytes,
+            total_bytes: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn path(&self, key: &ChunkKey) -> PathBuf {
+        self.dir.join(&key.0).join(key.1.to_string())
+    }
+
+    /// Marks the chunk as just used, returning its size if cached
+    fn touch(&mut self, key: &ChunkKey) -> Option<u64> {
+        let (size, last_used) = self.entries.get_mut(key)?;
+
+        self.tick += 1;
+        self.recency.remove(last_used);
+        *last_used = self.tick;
+        self.recency.insert(self.tick, key.clone());
+        Some(*size)
+    }
+
+    fn insert(&mut self, key: ChunkKey, size: u64) {
+        self.remove(&key);
+
+        self.tick += 1;
+        self.total_bytes += size;
+        self.entries.insert(key.clone(), (size, self.tick));
+        self.recency.insert(self.tick, key);
+    }
+
+    fn remove(&mut self, key: &ChunkKey) {
+        if let Some((size, last_used)) = self.entries.remove(key) {
+            self.total_bytes -= size;
+            self.recency.remove(&last_used);
+        }
+    }
+
+    /// Drops least recently used chunks until the cache fits its cap, returning their paths
+    fn evict(&mut self) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+        while self.total_bytes > self.max_bytes {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((size, _)) = self.entries.remove(&key) {
+                self.total_bytes -= size;
+            }
+            evicted.push(self.path(&key));
+        }
+        evicted
+    }
+}
+
+/// Only uuids are used as folder names, anything else could escape the cache directory
+fn is_cacheable(uuid: &str) -> bool {
+    !uuid.is_empty() && uuid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Whether `size` can be the length of an encrypted chunk
+fn is_chunk_len(size: u64) -> bool {
+    size > 0 && size <= CHUNK_SIZE as u64 + CHUNK_OVERHEAD
+}
+
+impl EncryptedChunkCache {
+    /// Returns the encrypted chunk `i` of the file `uuid`, if cached. Chunks whose length
+    /// differs from the one they were stored with are evicted instead of being served.
+    pub async fn get(&self, uuid: &str, i: u64) -> Option<Bytes> {
+        let (path, size) = self.lookup(uuid, i)?;
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(&path)?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            // Keeps the recency for the next start, failing to do so is harmless
+            let _ = file.set_modified(SystemTime::now());
+            Ok::<_, std::io::Error>(data)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(data)) if data.len() as u64 == size => Some(Bytes::from(data)),
+            _ => {
+                self.evict(uuid, i);
+                None
+            }
+        }
+    }
+
+    /// Copies the encrypted chunk `i` of the file `uuid` to `output`, if cached. Like `get`,
+    /// chunks of the wrong length are evicted.
+    pub async fn copy_to(&self, uuid: &str, i: u64, output: &str) -> bool {
+        let Some((path, size)) = self.lookup(uuid, i) else {
+            return false;
+        };
+
+        match tokio::fs::copy(&path, output).await {
+            Ok(copied) if copied == size => true,
+            _ => {
+                let _ = tokio::fs::remove_file(output).await;
+                self.evict(uuid, i);
+                false
+            }
+        }
+    }
+
+    /// Drops the encrypted chunk `i` of the file `uuid`, e.g. because it failed to decrypt
+    pub fn evict(&self, uuid: &str, i: u64) {
+        let path = {
+            let mut state = self.state.lock().unwrap();
+            let Some(state) = state.as_mut() else {
+                return;
+            };
+            if !is_cacheable(uuid) {
+                return;
+            }
+            let key = (uuid.to_string(), i);
+            state.remove(&key);
+            state.path(&key)
+        };
+
+        // Missing files are already gone
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Stores the encrypted chunk `i` of the file `uuid`. Errors only mean the chunk isn't
+    /// cached, so they are ignored.
+    pub async fn insert(&self, uuid: &str, i: u64, data: &[u8]) {
+        let Some(tmp_path) = self.prepare_insert(uuid, i, data.len() as u64).await else {
+            return;
+        };
+
+        let written = tokio::fs::write(&tmp_path, data).await.is_ok();
+        self.finish_insert(uuid, i, &tmp_path, written).await;
+    }
+
+    /// Same as `insert`, for a chunk that was downloaded to `input`
+    pub async fn insert_file(&self, uuid: &str, i: u64, input: &str) {
+        let Ok(metadata) = tokio::fs::metadata(input).await else {
+            return;
+        };
+        let Some(tmp_path) = self.prepare_insert(uuid, i, metadata.len()).await else {
+            return;
+        };
+
+        let written = tokio::fs::copy(input, &tmp_path).await.is_ok();
+        self.finish_insert(uuid, i, &tmp_path, written).await;
+    }
+
+    /// Path and size of a cached chunk
+    fn lookup(&self, uuid: &str, i: u64) -> Option<(PathBuf, u64)> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut()?;
+
+        let key = (uuid.to_string(), i);
+        let size = state.touch(&key)?;
+        Some((state.path(&key), size))
+    }
+
+    /// Creates the chunk's folder and returns a temporary path to write it to, so readers never
+    /// see partially written chunks
+    async fn prepare_insert(&self, uuid: &str, i: u64, size: u64) -> Option<PathBuf> {
+        let folder = {
+            let state = self.state.lock().unwrap();
+            let state = state.as_ref()?;
+            if !is_cacheable(uuid) || !is_chunk_len(size) || size > state.max_bytes {
+                return None;
+            }
+            state.dir.join(uuid)
+        };
+
+        tokio::fs::create_dir_all(&folder).await.ok()?;
+        Some(folder.join(format!("{}.{}.tmp", i, uuid::Uuid::new_v4())))
+    }
+
+    async fn finish_insert(&self, uuid: &str, i: u64, tmp_path: &Path, written: bool) {
+        let path = tmp_path.with_file_name(i.to_string());
+        let size = match tokio::fs::metadata(tmp_path).await {
+            Ok(metadata) if written => metadata.len(),
+            _ => {
+                let _ = tokio::fs::remove_file(tmp_path).await;
+                return;
+            }
+        };
+        if tokio::fs::rename(tmp_path, &path).await.is_err() {
+            let _ = tokio::fs::remove_file(tmp_path).await;
+            return;
+        }
+
+        let evicted = {
+            let mut state = self.state.lock().unwrap();
+            match state.as_mut() {
+                Some(state) if path.starts_with(&state.dir) => {
+                    state.insert((uuid.to_string(), i), size);
+                    state.evict()
+                }
+                // The cache was disabled or moved while writing
+                _ => vec![path],
+            }
+        };
+
+        for path in evicted {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    fn enable(&self, mut state: CacheState) -> Vec<PathBuf> {
+        let evicted = state.evict();
+        self.state.lock().unwrap().replace(state);
+        evicted
+    }
+}
+
+/// Indexes the chunks already in `dir`, oldest first. Leftover temporary files are removed.
+fn scan_cache_dir(dir: &Path, max_bytes: u64) -> std::io::Result<CacheState> {
+    let mut chunks = Vec::new();
+
+    for folder in std::fs::read_dir(dir)? {
+        let folder = folder?;
+        let Some(uuid) = folder.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if !is_cacheable(&uuid) || !folder.file_type()?.is_dir() {
+            continue;
+        }
+
+        for chunk in std::fs::read_dir(folder.path())? {
+            let chunk = chunk?;
+            let metadata = chunk.metadata()?;
+            let name = chunk.file_name();
+            match name.to_str().and_then(|name| name.parse::<u64>().ok()) {
+                Some(i) if metadata.is_file() && is_chunk_len(metadata.len()) => {
+                    chunks.push((metadata.modified()?, (uuid.clone(), i), metadata.len()));
+                }
+                // Chunks that can't be valid are dropped along with leftover temporary files
+                Some(_) if metadata.is_file() => std::fs::remove_file(chunk.path())?,
+                _ => {
+                    if name.to_string_lossy().ends_with(".tmp") {
+                        std::fs::remove_file(chunk.path())?;
+                    }
+                }
+            }
+        }
+    }
+
+    chunks.sort_by_key(|(modified, _, _)| *modified);
+    let mut state = CacheState::new(dir.to_path_buf(), max_bytes);
+    for (_, key, size) in chunks {
+        state.insert(key, size);
+    }
+    Ok(state)
+}
+
+#[uniffi::export]
+impl FilenSDK {
+    /// Stops consulting and filling the chunk cache. Cached chunks stay on disk.
+    pub fn disable_chunk_cache(&self) {
+        self.chunk_cache.state.lock().unwrap().take();
+    }
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Caches encrypted chunks in `dir`, using at most `max_bytes`, see `EncryptedChunkCache`.
+    /// Chunks cached in `dir` by a previous session are reused.
+    pub async fn enable_chunk_cache(&self, dir: String, max_bytes: u64) -> Result<(), FilenSDKError> {
+        let dir = PathBuf::from(dir);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let state = tokio::task::spawn_blocking(move || scan_cache_dir(&dir, max_bytes)).await??;
+        for path in self.chunk_cache.enable(state) {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        Ok(())
+    }
+
+    /// Removes every cached chunk, the cache stays enabled
+    pub async fn clear_chunk_cache(&self) -> Result<(), FilenSDKError> {
+        let dir = {
+            let mut state = self.chunk_cache.state.lock().unwrap();
+            let Some(state) = state.as_mut() else {
+                return Ok(());
+            };
+            *state = CacheState::new(state.dir.clone(), state.max_bytes);
+            state.dir.clone()
+        };
+
+        let mut folders = tokio::fs::read_dir(&dir).await?;
+        while let Some(folder) = folders.next_entry().await? {
+            let is_chunk_folder = folder.file_name().to_str().is_some_and(is_cacheable);
+            if is_chunk_folder && folder.file_type().await?.is_dir() {
+                tokio::fs::remove_dir_all(folder.path()).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_dir() -> String {
+        std::env::temp_dir()
+            .join(format!("chunk-cache-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_insert_get_and_evict() {
+        let sdk = FilenSDK::new();
+        let dir = cache_dir();
+        let cache = &sdk.chunk_cache;
+
+        // Disabled caches store nothing
+        cache.insert("a", 0, b"chunk").await;
+        assert!(cache.get("a", 0).await.is_none());
+
+        sdk.enable_chunk_cache(dir.clone(), 10).await.unwrap();
+        cache.insert("a", 0, b"0000").await;
+        cache.insert("a", 1, b"1111").await;
+        assert_eq!(cache.get("a", 0).await.unwrap(), Bytes::from_static(b"0000"));
+
+        // Chunk 1 is the least recently used, so it is evicted
+        cache.insert("b", 0, b"2222").await;
+        assert!(cache.get("a", 1).await.is_none());
+        assert!(!Path::new(&dir).join("a").join("1").exists());
+        assert!(cache.get("a", 0).await.is_some());
+        assert!(cache.get("b", 0).await.is_some());
+
+        // Names that could escape the cache directory and oversized chunks are not cached
+        cache.insert("../x", 0, b"x").await;
+        assert!(cache.get("../x", 0).await.is_none());
+        cache.insert("c", 0, &[0; 11]).await;
+        assert!(cache.get("c", 0).await.is_none());
+
+        let output = Path::new(&dir).join("copy");
+        assert!(cache.copy_to("b", 0, output.to_str().unwrap()).await);
+        assert_eq!(std::fs::read(&output).unwrap(), b"2222");
+
+        sdk.clear_chunk_cache().await.unwrap();
+        assert!(cache.get("a", 0).await.is_none());
+        assert!(!Path::new(&dir).join("a").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_funcs_use_cache() {
+        use crate::{
+            httpclient::FsURL,
+            mod_private::net_interaction::{FilenNetInteractionFunctions, LowDiskInteractionFunctions},
+        };
+
+        let sdk = FilenSDK::new();
+        let dir = cache_dir();
+        sdk.enable_chunk_cache(dir.clone(), 1024).await.unwrap();
+        sdk.chunk_cache.insert("uuid", 3, b"ciphertext").await;
+
+        // Served from the cache, without touching the network
+        let funcs = LowDiskInteractionFunctions {
+            client: sdk.client.clone(),
+            api_key: "".to_string(),
+            should_use_counter_nonce: false,
+            chunk_cache: Some(sdk.chunk_cache.clone()),
+        };
+        let link = FsURL::Egest("region".to_string(), "bucket".to_string(), "uuid".to_string(), 3);
+        let data = funcs.http_retrieve_data(link.clone(), 3).await.unwrap();
+        assert_eq!(data, Bytes::from_static(b"ciphertext"));
+
+        // A chunk that fails to decrypt is evicted, so the next attempt goes to egest
+        assert!(FilenSDK::decrypt_chunk(&link, 3, data, &[0; 32], &funcs).is_err());
+        assert!(sdk.chunk_cache.get("uuid", 3).await.is_none());
+        assert!(!Path::new(&dir).join("uuid").join("3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_truncated_chunks_are_evicted() {
+        let sdk = FilenSDK::new();
+        let dir = cache_dir();
+        let cache = &sdk.chunk_cache;
+        sdk.enable_chunk_cache(dir.clone(), 1024).await.unwrap();
+
+        cache.insert("a", 0, b"0000").await;
+        cache.insert("a", 1, b"1111").await;
+        std::fs::write(Path::new(&dir).join("a").join("0"), b"00").unwrap();
+        std::fs::write(Path::new(&dir).join("a").join("1"), b"11").unwrap();
+
+        assert!(cache.get("a", 0).await.is_none());
+        assert!(!Path::new(&dir).join("a").join("0").exists());
+
+        let output = Path::new(&dir).join("copy");
+        assert!(!cache.copy_to("a", 1, output.to_str().unwrap()).await);
+        assert!(!output.exists());
+        assert!(!Path::new(&dir).join("a").join("1").exists());
+
+        // Chunks that can't be valid are dropped when the cache is opened
+        std::fs::write(Path::new(&dir).join("a").join("2"), b"").unwrap();
+        let sdk = FilenSDK::new();
+        sdk.enable_chunk_cache(dir.clone(), 1024).await.unwrap();
+        assert!(!Path::new(&dir).join("a").join("2").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cache_survives_restart() {
+        let dir = cache_dir();
+
+        let sdk = FilenSDK::new();
+        sdk.enable_chunk_cache(dir.clone(), 1024).await.unwrap();
+        sdk.chunk_cache.insert("a", 0, b"0000").await;
+        sdk.chunk_cache.insert("a", 1, b"1111").await;
+        std::fs::write(Path::new(&dir).join("a").join("2.leftover.tmp"), b"x").unwrap();
+
+        // Reopening with a smaller cap evicts the oldest chunk and cleans up temporary files
+        let sdk = FilenSDK::new();
+        sdk.enable_chunk_cache(dir.clone(), 4).await.unwrap();
+        assert!(sdk.chunk_cache.get("a", 0).await.is_none());
+        assert_eq!(sdk.chunk_cache.get("a", 1).await.unwrap(), Bytes::from_static(b"1111"));
+        assert!(!Path::new(&dir).join("a").join("2.leftover.tmp").exists());
+
+        sdk.disable_chunk_cache();
+        assert!(sdk.chunk_cache.get("a", 1).await.is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
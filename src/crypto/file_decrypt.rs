@@ -4,7 +4,6 @@ use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use bytes::{Bytes, BytesMut};
 use ring::aead::{self};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 #[deprecated(
     since = "1.0.0",
@@ -83,20 +82,30 @@ pub fn write_output(output: &Path, data: &[u8], index: Option<usize>) -> Result<
     Ok(())
 }
 
-// Use tokio to create async writes
-pub async fn write_output_async(output: &Path, data: &[u8], index: Option<usize>) -> Result<(), Box<dyn Error>> {
-    let mut output_file = tokio::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(output).await?;
-
-    if let Some(idx) = index {
-        let offset = (idx * super::CHUNK_SIZE) as u64;
-        output_file.seek(SeekFrom::Start(offset)).await?;
+/// Writes data at byte `offset` through a shared file handle without moving a cursor, so that
+/// a single handle can be used for every chunk of a download.
+pub fn write_output_at(output: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::write_all_at(output, data, offset)
     }
 
-    output_file.write_all(data).await?;
-    Ok(())
+    #[cfg(windows)]
+    {
+        let mut written = 0;
+        while written < data.len() {
+            let n = std::os::windows::fs::FileExt::seek_write(
+                output,
+                &data[written..],
+                offset + written as u64,
+            )?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero));
+            }
+            written += n;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -143,6 +152,23 @@ mod tests{
     fn test_send() {
         assert_send(decrypt_v2_bytes);
         assert_send(write_output);
-        assert_send(write_output_async);
+        assert_send(write_output_at);
+    }
+
+    #[test]
+    fn test_write_output_at() {
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        let file = OpenOptions::new().create(true).truncate(true).write(true).open(&path).unwrap();
+        file.set_len(super::super::CHUNK_SIZE as u64 + 3).unwrap();
+
+        // Out of order writes through the same handle
+        write_output_at(&file, b"xyz", super::super::CHUNK_SIZE as u64).unwrap();
+        write_output_at(&file, b"abc", 0).unwrap();
+        drop(file);
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(&data[..3], b"abc");
+        assert_eq!(&data[super::super::CHUNK_SIZE..], b"xyz");
+        remove_file(&path).unwrap();
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::{
     error::Error,
-    io::{self, Read, Seek, Write},
+    io::{self, Write},
 };
 
 use bytes::Bytes;
@@ -8,32 +8,27 @@ use ring::{
     aead::{self, chacha20_poly1305_openssh::TAG_LEN, Tag}, digest::{digest, SHA512}, rand::{self, SecureRandom}
 };
 
+use crate::mod_private::integrity::OrderedHasher;
+
 use super::{generate_counter_iv, CryptoError, CHUNK_SIZE};
 
 /*
 Optimized encrypt function that prevents copying too much within memory.
-This function reads data from a file and encrypts it in place and then writes it to an output file
-if it exists. Otherwise, it returns the encrypted data.
+This function reads chunk `index` from `input` and encrypts it in place and then writes it to an
+output file if it exists. Otherwise, it returns the encrypted data. Chunks are read at their
+offset without moving the file cursor, so one handle can be shared by concurrent workers. The
+plaintext is handed to `plaintext_hasher` before it is encrypted, if one is given.
 */
 pub fn encrypt_v2_from_file(
-    input: &str,
+    input: &std::fs::File,
     output: Option<&str>,
     key_bytes: &[u8; 32],
     index: usize,
     should_use_counter_nonce: bool,
+    plaintext_hasher: Option<&OrderedHasher>,
 ) -> Result<(Option<Bytes>, String), CryptoError> {
-    // Does file exist
-    if !std::path::Path::new(input).exists() {
-        return Err(CryptoError::Io(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Input file not found",
-        )));
-    }
-
-    // Open file and verify index
-    let mut input_file = std::fs::File::open(input)?;
-    let metadata = input_file.metadata()?;
-    let file_size = metadata.len().try_into().unwrap();
+    // Verify index
+    let file_size: usize = input.metadata()?.len().try_into().unwrap();
 
     if index * CHUNK_SIZE > file_size {
         return Err(CryptoError::Io(io::Error::new(
@@ -49,10 +44,17 @@ pub fn encrypt_v2_from_file(
     };
 
     let mut data: Vec<u8> = vec![0; (size_of_chunk + 12 + TAG_LEN) as usize];
-    input_file.seek(io::SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))?;
 
     let range_of_data = 12..(size_of_chunk + 12);
-    input_file.read_exact(&mut data[range_of_data.clone()])?;
+    read_exact_at(input, &mut data[range_of_data.clone()], index as u64 * CHUNK_SIZE as u64)?;
+    if let Some(plaintext_hasher) = plaintext_hasher {
+        if !plaintext_hasher.update(index as u64, &data[range_of_data.clone()]) {
+            return Err(CryptoError::Io(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Encryption of an earlier chunk failed",
+            )));
+        }
+    }
 
     let (nonce, tag) = encrypt_v2_in_memory(&mut data[range_of_data], key_bytes, index, should_use_counter_nonce)?;
 
@@ -81,6 +83,52 @@ pub fn encrypt_v2_from_file(
     }
 }
 
+/// Reads exactly `buf.len()` bytes at `offset` without using the file cursor
+pub(crate) fn read_exact_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(file, &mut buf[filled..], offset + filled as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                read => filled += read,
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+Encrypts a chunk that is already in memory, e.g. when the source is not a file. The output has
+the same layout as `encrypt_v2_from_file`: nonce, ciphertext, tag.
+*/
+pub fn encrypt_v2_bytes(
+    plaintext: &[u8],
+    key_bytes: &[u8; 32],
+    index: usize,
+    should_use_counter_nonce: bool,
+) -> Result<(Bytes, String), CryptoError> {
+    let size_of_chunk = plaintext.len();
+    let mut data: Vec<u8> = vec![0; size_of_chunk + 12 + TAG_LEN];
+
+    let range_of_data = 12..(size_of_chunk + 12);
+    data[range_of_data.clone()].copy_from_slice(plaintext);
+
+    let (nonce, tag) = encrypt_v2_in_memory(&mut data[range_of_data], key_bytes, index, should_use_counter_nonce)?;
+
+    data[size_of_chunk + 12..].copy_from_slice(tag.as_ref());
+    data[0..12].copy_from_slice(&nonce);
+
+    let hash = hex::encode(digest(&SHA512, &data));
+
+    Ok((Bytes::from(data), hash))
+}
+
 /*
 The lowest level function for encrypting data in memory.
 This function encrypts the data in place and returns the nonce, key, and tag used.
@@ -163,7 +211,7 @@ mod tests {
             memory_stats().unwrap().physical_mem / 1024
         );
         let key = generate_rand_key().unwrap();
-        let _ = encrypt_v2_from_file(input, Some(output), &key, 0, true).unwrap();
+        let _ = encrypt_v2_from_file(&std::fs::File::open(input).unwrap(), Some(output), &key, 0, true, None).unwrap();
         println!(
             "Current memory usage: {} MB after encrypt",
             memory_stats().unwrap().physical_mem / 1024
@@ -194,6 +242,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_encrypt_v2_bytes() {
+        let key = generate_rand_key().unwrap();
+        let plaintext = vec![0x42; 1000];
+
+        let (data, hash) = encrypt_v2_bytes(&plaintext, &key, 3, true).unwrap();
+        assert_eq!(hash, hex::encode(digest(&SHA512, &data)));
+        assert_eq!(&data[0..12], &generate_counter_iv(3));
+
+        let mut data = data.try_into_mut().unwrap();
+        let decrypted = decrypt_v2_bytes(&mut data, &key).unwrap();
+        assert_eq!(&decrypted[..], &plaintext[..]);
+    }
+
     #[test]
     fn test_encrypt_file_in_memory() {
         let input = "tests/out/test.txt";
@@ -203,7 +265,7 @@ mod tests {
             memory_stats().unwrap().physical_mem / 1024
         );
         let key = generate_rand_key().unwrap();
-        let data = encrypt_v2_from_file(input, None, &key, 0, false).unwrap();
+        let data = encrypt_v2_from_file(&std::fs::File::open(input).unwrap(), None, &key, 0, false, None).unwrap();
         let current_memory_after_encrypt = memory_stats().unwrap().physical_mem / 1024;
         println!(
             "Current memory usage: {} MB after encrypt",
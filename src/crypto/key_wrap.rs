@@ -0,0 +1,95 @@
+use std::num::NonZero;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::CryptoError;
+
+const WRAP_PBKDF2_ROUNDS: u32 = 200000;
+const WRAP_SALT_LEN: usize = 16;
+
+/// A key encrypted with AES-256-GCM under a key derived from a passphrase with
+/// PBKDF2-HMAC-SHA512. Binary fields are base64 encoded.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct WrappedKey {
+    pub salt: String,
+    pub rounds: u32,
+    pub iv: String,
+    pub ciphertext: String,
+}
+
+fn derive_wrapping_key(passphrase: &str, salt: &[u8], rounds: u32) -> Result<ring::aead::LessSafeKey, CryptoError> {
+    let rounds = NonZero::new(rounds).ok_or(CryptoError::InvalidMetadata)?;
+
+    let mut derived_key = [0u8; 32];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA512,
+        rounds,
+        salt,
+        passphrase.as_bytes(),
+        &mut derived_key,
+    );
+
+    Ok(ring::aead::LessSafeKey::new(
+        ring::aead::UnboundKey::new(&ring::aead::AES_256_GCM, &derived_key)?
+    ))
+}
+
+pub fn wrap_key(key: &[u8], passphrase: &str) -> Result<WrappedKey, CryptoError> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; WRAP_SALT_LEN];
+    rng.fill(&mut salt)?;
+    let mut iv = [0u8; 12];
+    rng.fill(&mut iv)?;
+
+    let sealing_key = derive_wrapping_key(passphrase, &salt, WRAP_PBKDF2_ROUNDS)?;
+    let mut data = key.to_vec();
+    sealing_key.seal_in_place_append_tag(
+        ring::aead::Nonce::assume_unique_for_key(iv),
+        ring::aead::Aad::empty(),
+        &mut data,
+    )?;
+
+    Ok(WrappedKey {
+        salt: BASE64_STANDARD.encode(salt),
+        rounds: WRAP_PBKDF2_ROUNDS,
+        iv: BASE64_STANDARD.encode(iv),
+        ciphertext: BASE64_STANDARD.encode(data),
+    })
+}
+
+/// Fails with `CryptoError::Ring` when the passphrase is wrong
+pub fn unwrap_key(wrapped: &WrappedKey, passphrase: &str) -> Result<Vec<u8>, CryptoError> {
+    let decode = |field: &str| BASE64_STANDARD.decode(field).map_err(|_| CryptoError::InvalidMetadata);
+    let salt = decode(&wrapped.salt)?;
+    let iv: [u8; 12] = decode(&wrapped.iv)?
+        .try_into()
+        .map_err(|_| CryptoError::InvalidMetadata)?;
+    let mut data = decode(&wrapped.ciphertext)?;
+
+    let opening_key = derive_wrapping_key(passphrase, &salt, wrapped.rounds)?;
+    let key = opening_key.open_in_place(
+        ring::aead::Nonce::assume_unique_for_key(iv),
+        ring::aead::Aad::empty(),
+        &mut data,
+    )?;
+
+    Ok(key.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap_key() {
+        let key = crate::crypto::generate_rand_key().unwrap();
+        let wrapped = wrap_key(&key, "correct horse").unwrap();
+
+        assert_eq!(unwrap_key(&wrapped, "correct horse").unwrap(), key);
+        assert!(unwrap_key(&wrapped, "wrong horse").is_err());
+
+        // Salts and ivs are random, so wrapping twice never matches
+        assert_ne!(wrap_key(&key, "correct horse").unwrap(), wrapped);
+    }
+}
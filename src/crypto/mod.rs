@@ -6,8 +6,11 @@ pub mod password;
 pub mod file_decrypt;
 pub mod file_encrypt;
 pub mod metadata;
+pub mod key_wrap;
 
 pub const CHUNK_SIZE: usize = 1024 * 1024;
+/// Bytes an encrypted chunk has on top of its plaintext, the iv and the GCM tag
+pub(crate) const CHUNK_OVERHEAD: u64 = 12 + 16;
 
 #[derive(Debug)]
 pub enum CryptoError {
@@ -1,5 +1,6 @@
 use std::sync::{Arc, Mutex};
 
+use futures::TryStreamExt;
 use streamed_json::iter_json_array;
 use uniffi::FfiConverter;
 use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
@@ -7,9 +8,16 @@ use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
 use crate::{
     error::FilenSDKError,
     file::FilenFileDetailed,
-    httpclient::{httpclient::construct_request, Endpoints},
-    requests::fs::DirContentBody,
-    responses::fs::StreamedDirContentResponse,
+    httpclient::{httpclient::construct_request, make_request, Endpoints},
+    requests::fs::{
+        DirContentBody, DirCreateBody, DirInfoBody, DirSizeBody, ExistsBody, FileMetadata,
+        FolderMetadata,
+    },
+    responses::fs::{
+        DirContentResponse, DirCreateResponse, DirGetResponse, DirSizeResponse, ExistsResponse,
+        StreamedDirContentResponse,
+    },
+    tree::FilenWalkOptions,
     FilenSDK,
 };
 
@@ -46,50 +54,105 @@ impl DirContentsIterator {
         &self,
         response: StreamedDirContentResponse,
     ) -> Result<DecryptedStreamedDirContentResponse, FilenSDKError> {
-        match response {
-            StreamedDirContentResponse::Uploads(upload) => {
-                let decrypted_metadata =
-                    FilenSDK::decrypt_metadata(upload.metadata, self.master_key.clone())?;
-                let name = decrypted_metadata.name.clone();
-                let path_for_name = std::path::Path::new(&name);
-                Ok(DecryptedStreamedDirContentResponse::Uploads(
-                    FilenFileDetailed {
-                        uuid: upload.uuid,
-                        region: upload.region,
-                        bucket: upload.bucket,
-                        name: decrypted_metadata.name,
-                        size: upload.size,
-                        mime: decrypted_metadata.mime.unwrap_or(
-                            mime_guess::from_path(path_for_name)
-                                .first()
-                                .map(|m| m.to_string())
-                                .unwrap_or("".to_owned()),
-                        ),
-                        key: decrypted_metadata.key,
-                        last_modified: decrypted_metadata.last_modified,
-                        parent: upload.parent,
-                        versioned: None,
-                        trash: false,
-                        version: upload.version,
-                    },
-                ))
-            }
-            StreamedDirContentResponse::Folders(folder) => Ok(
-                DecryptedStreamedDirContentResponse::Folders(FilenFolderDetailed {
-                    uuid: folder.uuid,
-                    name: String::from_utf8(crate::crypto::metadata::decrypt_metadata(
-                        &folder.name.as_bytes(),
-                        &self.master_key,
-                    )?)?,
-                    parent: folder.parent,
-                    color: folder.color,
-                    timestamp: folder.timestamp,
-                    favorited: folder.favorited,
-                    is_sync: folder.is_sync,
-                    is_default: folder.is_default,
-                }),
-            ),
+        decrypt_dir_content(response, &self.master_key)
+    }
+}
+
+/// Decrypts a single entry of a directory listing with the user's master key.
+pub(crate) fn decrypt_dir_content(
+    response: StreamedDirContentResponse,
+    master_key: &str,
+) -> Result<DecryptedStreamedDirContentResponse, FilenSDKError> {
+    match response {
+        StreamedDirContentResponse::Uploads(upload) => {
+            let decrypted_metadata = decrypt_file_metadata(upload.metadata, master_key)?;
+            Ok(DecryptedStreamedDirContentResponse::Uploads(
+                FilenFileDetailed {
+                    uuid: upload.uuid,
+                    region: upload.region,
+                    bucket: upload.bucket,
+                    name: decrypted_metadata.name,
+                    size: upload.size,
+                    mime: decrypted_metadata.mime.unwrap_or_default(),
+                    key: decrypted_metadata.key,
+                    last_modified: decrypted_metadata.last_modified,
+                    hash: decrypted_metadata.hash,
+                    parent: upload.parent,
+                    versioned: None,
+                    trash: false,
+                    version: upload.version,
+                },
+            ))
         }
+        StreamedDirContentResponse::Folders(folder) => Ok(
+            DecryptedStreamedDirContentResponse::Folders(FilenFolderDetailed {
+                uuid: folder.uuid,
+                name: decrypt_folder_name(&folder.name, master_key)?,
+                parent: folder.parent,
+                color: folder.color,
+                timestamp: folder.timestamp,
+                favorited: folder.favorited,
+                is_sync: folder.is_sync,
+                is_default: folder.is_default,
+            }),
+        ),
+    }
+}
+
+/// Decrypts the metadata of a listed file with the user's master key. Files stored without a mime
+/// type get one guessed from their name, `application/octet-stream` if nothing matches, so every
+/// listing reports the same type for them.
+pub(crate) fn decrypt_file_metadata(metadata: String, master_key: &str) -> Result<FileMetadata, FilenSDKError> {
+    let mut metadata = FilenSDK::decrypt_metadata(metadata, master_key.to_string())?;
+    metadata
+        .mime
+        .get_or_insert_with(|| mime_guess::from_path(&metadata.name).first_or_octet_stream().to_string());
+    Ok(metadata)
+}
+
+/// Shared implementation of `dir_contents` for callers that hold cloned credentials rather than a
+/// reference to the SDK (e.g. spawned tasks).
+pub(crate) async fn fetch_dir_contents(
+    client: &reqwest::Client,
+    api_key: &str,
+    master_key: &str,
+    uuid: String,
+    folders_only: bool,
+) -> Result<Vec<DecryptedStreamedDirContentResponse>, FilenSDKError> {
+    let response: DirContentResponse = make_request(
+        Endpoints::DirContent,
+        Some(client),
+        None,
+        Some(api_key),
+        Some(DirContentBody { uuid, folders_only }),
+    )
+    .await?;
+
+    response
+        .folders
+        .into_iter()
+        .map(StreamedDirContentResponse::Folders)
+        .chain(
+            response
+                .uploads
+                .into_iter()
+                .map(StreamedDirContentResponse::Uploads),
+        )
+        .map(|entry| decrypt_dir_content(entry, master_key))
+        .collect()
+}
+
+/// Folder names are encrypted as `{"name": "..."}`, but older clients stored the raw name, so
+/// fall back to the decrypted string when it is not a JSON object.
+pub(crate) fn decrypt_folder_name(name: &str, master_key: &str) -> Result<String, FilenSDKError> {
+    let decrypted = String::from_utf8(crate::crypto::metadata::decrypt_metadata(
+        name.as_bytes(),
+        master_key,
+    )?)?;
+
+    match serde_json::from_str::<FolderMetadata>(&decrypted) {
+        Ok(metadata) => Ok(metadata.name),
+        Err(_) => Ok(decrypted),
     }
 }
 
@@ -116,24 +179,64 @@ impl DirContentsIterator {
     }
 }
 
-#[derive(uniffi::Record)]
+#[derive(uniffi::Record, Debug, Clone)]
 pub struct FilenFolderDetailed {
-    uuid: String,
-    name: String,
-    parent: String,
-    color: Option<String>,
-    timestamp: u64,
-    favorited: u64,
-    is_sync: Option<u64>,
-    is_default: Option<u64>,
+    pub uuid: String,
+    pub name: String,
+    pub parent: String,
+    pub color: Option<String>,
+    pub timestamp: u64,
+    pub favorited: u64,
+    pub is_sync: Option<u64>,
+    pub is_default: Option<u64>,
 }
 
-#[derive(uniffi::Enum)]
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenFolderInfo {
+    pub uuid: String,
+    pub name: String,
+    pub parent: String,
+}
+
+/// Aggregate size of a folder and everything below it
+#[derive(uniffi::Record, Debug, Clone, Default, PartialEq)]
+pub struct FilenFolderSize {
+    pub bytes: u64,
+    pub files: u64,
+    pub folders: u64,
+    /// Whether the tree was walked client side because the size endpoint was unavailable
+    pub walked: bool,
+}
+
+#[derive(uniffi::Enum, Debug, Clone)]
 pub enum DecryptedStreamedDirContentResponse {
     Uploads(FilenFileDetailed),
     Folders(FilenFolderDetailed),
 }
 
+impl DecryptedStreamedDirContentResponse {
+    pub fn uuid(&self) -> &str {
+        match self {
+            DecryptedStreamedDirContentResponse::Uploads(file) => &file.uuid,
+            DecryptedStreamedDirContentResponse::Folders(folder) => &folder.uuid,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            DecryptedStreamedDirContentResponse::Uploads(file) => &file.name,
+            DecryptedStreamedDirContentResponse::Folders(folder) => &folder.name,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        match self {
+            DecryptedStreamedDirContentResponse::Uploads(_) => false,
+            DecryptedStreamedDirContentResponse::Folders(_) => true,
+        }
+    }
+}
+
 #[uniffi_async_export]
 impl FilenSDK {
     pub async fn dir_contents_iter(
@@ -160,4 +263,185 @@ impl FilenSDK {
 
         Ok(DirContentsIterator::new(Box::new(json_iter), self.master_key()?))
     }
+
+    /// Lists and decrypts the direct children of a folder in a single request. Unlike
+    /// `dir_contents_iter`, the whole listing is buffered, which makes it usable from async code
+    /// without blocking a thread.
+    pub async fn dir_contents(
+        &self,
+        uuid: String,
+        folders_only: bool,
+    ) -> Result<Vec<DecryptedStreamedDirContentResponse>, FilenSDKError> {
+        fetch_dir_contents(
+            &self.client,
+            &self.api_key()?,
+            &self.master_key()?,
+            uuid,
+            folders_only,
+        )
+        .await
+    }
+
+    /// Retrieves the decrypted name and parent of a folder.
+    pub async fn dir_info(&self, uuid: String) -> Result<FilenFolderInfo, FilenSDKError> {
+        let response: DirGetResponse = make_request(
+            Endpoints::DirInfo,
+            Some(&self.client),
+            None,
+            Some(&self.api_key()?),
+            Some(DirInfoBody { uuid }),
+        )
+        .await?;
+
+        Ok(FilenFolderInfo {
+            name: decrypt_folder_name(&response.name_encrypted, &self.master_key()?)?,
+            uuid: response.uuid,
+            parent: response.parent,
+        })
+    }
+
+    /// Returns the total size and number of files and folders below `uuid`. The gateway's size
+    /// endpoint is used when available, otherwise the tree is walked and aggregated client side,
+    /// see `FilenFolderSize::walked`. Other errors of the endpoint, such as an invalid API key,
+    /// are returned without walking the tree.
+    pub async fn folder_size(&self, uuid: String) -> Result<FilenFolderSize, FilenSDKError> {
+        let response: Result<DirSizeResponse, FilenSDKError> = make_request(
+            Endpoints::DirSize,
+            Some(&self.client),
+            None,
+            Some(&self.api_key()?),
+            Some(DirSizeBody {
+                uuid: uuid.clone(),
+                sharer_id: 0,
+                receiver_id: 0,
+            }),
+        )
+        .await;
+
+        match response {
+            Ok(response) => Ok(FilenFolderSize {
+                bytes: response.size,
+                files: response.files,
+                folders: response.folders,
+                walked: false,
+            }),
+            Err(err) if is_dir_size_unavailable(&err) => self.folder_size_walked(uuid).await,
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Client side `folder_size`, walks the whole tree and sums up the decrypted file sizes.
+    pub async fn folder_size_walked(&self, uuid: String) -> Result<FilenFolderSize, FilenSDKError> {
+        let walked = FilenFolderSize {
+            walked: true,
+            ..Default::default()
+        };
+        self.walk_tree(uuid, FilenWalkOptions::default())
+            .try_fold(walked, |mut size, entry| async move {
+                match entry.item {
+                    DecryptedStreamedDirContentResponse::Uploads(file) => {
+                        size.bytes += file.size;
+                        size.files += 1;
+                    }
+                    DecryptedStreamedDirContentResponse::Folders(_) => size.folders += 1,
+                }
+                Ok(size)
+            })
+            .await
+    }
+
+    /// Checks whether a folder named `name` exists directly inside `parent`, returning its uuid
+    /// if it does. See `file_exists`.
+    pub async fn folder_exists(
+        &self,
+        parent: String,
+        name: String,
+    ) -> Result<Option<String>, FilenSDKError> {
+        let response: ExistsResponse = make_request(
+            Endpoints::DirExists,
+            Some(&self.client),
+            None,
+            Some(&self.api_key()?),
+            Some(ExistsBody {
+                parent,
+                name_hashed: crate::crypto::metadata::hash_fn(&name.to_lowercase())?,
+            }),
+        )
+        .await?;
+
+        Ok(response.uuid.filter(|_| response.exists))
+    }
+
+    /// Creates a folder named `name` inside `parent` and returns the uuid of the new folder.
+    pub async fn create_folder(&self, parent: String, name: String) -> Result<String, FilenSDKError> {
+        let master_key = self.master_key()?;
+        let metadata = serde_json::to_string(&FolderMetadata { name: name.clone() })?;
+        let name_enc = crate::crypto::metadata::encrypt_metadata(metadata.as_bytes(), &master_key)?;
+
+        let response: DirCreateResponse = make_request(
+            Endpoints::DirCreate,
+            Some(&self.client),
+            None,
+            Some(&self.api_key()?),
+            Some(DirCreateBody {
+                uuid: uuid::Uuid::new_v4().to_string(),
+                name: String::from_utf8(name_enc)?,
+                name_hashed: crate::crypto::metadata::hash_fn(&name.to_lowercase())?,
+                parent,
+            }),
+        )
+        .await?;
+
+        Ok(response.uuid)
+    }
+}
+
+/// Whether a failed dir size request means the gateway does not offer the endpoint or returned
+/// no data for it, rather than e.g. an invalid API key or uuid. A missing endpoint answers with
+/// a non JSON error page or an error without a code.
+fn is_dir_size_unavailable(err: &FilenSDKError) -> bool {
+    match err {
+        FilenSDKError::SerdeJsonError { .. } => true,
+        FilenSDKError::APIError { code, .. } => code.as_deref().is_none_or(|code| code == "endpoint_not_found"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_dir_size_unavailable() {
+        let api_error = |code: Option<&str>| FilenSDKError::APIError {
+            message: String::new(),
+            code: code.map(str::to_string),
+        };
+
+        assert!(is_dir_size_unavailable(&FilenSDKError::SerdeJsonError {
+            err_msg: String::new(),
+            err_str: "<html>404</html>".to_string(),
+        }));
+        assert!(is_dir_size_unavailable(&api_error(None)));
+        assert!(is_dir_size_unavailable(&api_error(Some("endpoint_not_found"))));
+
+        assert!(!is_dir_size_unavailable(&api_error(Some("api_key_not_found"))));
+        assert!(!is_dir_size_unavailable(&api_error(Some("folder_not_found"))));
+        assert!(!is_dir_size_unavailable(&FilenSDKError::ReqwestError { err_str: String::new() }));
+        assert!(!is_dir_size_unavailable(&FilenSDKError::NoCredentials));
+    }
+
+    #[test]
+    fn test_decrypt_file_metadata_mime() {
+        let master_key = "abcdabcdabcdabcdabcdabcdabcdabcd";
+        let mime = |metadata: serde_json::Value| {
+            let encrypted = crate::crypto::metadata::encrypt_metadata(metadata.to_string().as_bytes(), master_key).unwrap();
+            decrypt_file_metadata(String::from_utf8(encrypted).unwrap(), master_key).unwrap().mime
+        };
+        let key = "k".repeat(32);
+
+        assert_eq!(mime(serde_json::json!({ "name": "a.png", "key": key, "mime": "text/plain" })).as_deref(), Some("text/plain"));
+        assert_eq!(mime(serde_json::json!({ "name": "a.png", "key": key })).as_deref(), Some("image/png"));
+        assert_eq!(mime(serde_json::json!({ "name": "a.unknown", "key": key })).as_deref(), Some("application/octet-stream"));
+    }
 }
@@ -1,9 +1,18 @@
+use std::{io::Write, pin::pin, sync::Arc};
+
 use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
 
 use crate::{
+    error::FilenSDKError,
     file::FilenFileDetailed,
-    mod_private::net_interaction::{LowDiskInteractionFunctions, LowMemoryInteractionFunctions},
+    httpclient::{ChunkSource, OrderlessDownloadOptions},
+    mod_private::{
+        integrity::{verified_stream, PlaintextHasher},
+        net_interaction::{LowDiskInteractionFunctions, LowMemoryInteractionFunctions},
+    },
     FilenSDK,
 };
 
@@ -19,6 +28,37 @@ pub struct FilenFileDownloadResult {
     pub file_info: FilenFileDetailed,
 }
 
+/// Outcome of `verify_file`
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenFileVerification {
+    pub file_info: FilenFileDetailed,
+    /// Whether the plaintext was checked against a stored hash. Files uploaded without one are
+    /// only checked for their size.
+    pub hash_verified: bool,
+}
+
+/// Receives the plaintext of a download in order, see `download_to_callback`. Implemented by
+/// foreign code, e.g. to pipe a download into a platform stream.
+#[uniffi::export(with_foreign)]
+pub trait FilenDownloadWriter: Send + Sync {
+    fn write(&self, data: Vec<u8>) -> Result<(), FilenSDKError>;
+    fn flush(&self) -> Result<(), FilenSDKError>;
+}
+
+/// Adapts a foreign `FilenDownloadWriter` to `std::io::Write`
+struct ForeignWriter(Arc<dyn FilenDownloadWriter>);
+
+impl Write for ForeignWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf.to_vec()).map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().map_err(std::io::Error::other)
+    }
+}
+
 macro_rules! extract_path_and_filename {
     ($output_file:expr) => {{
         let file_path = std::path::Path::new(&$output_file);
@@ -65,25 +105,30 @@ impl FilenSDK {
     ) -> Result<FileByteRange, crate::error::FilenSDKError> {
         let client = self.client.clone();
 
-        let start_byte = start_byte.unwrap_or(0);
-        let end_byte = end_byte.unwrap_or(file_size);
-
         let output_dir = std::path::Path::new(&output_dir);
 
         self.orderless_file_download(
-            &uuid,
-            &region,
-            &bucket,
-            key,
+            ChunkSource {
+                uuid,
+                region,
+                bucket,
+                key,
+                size: file_size,
+                hash: None,
+            },
             output_dir,
-            output_filename,
-            file_size,
-            start_byte,
-            end_byte,
+            OrderlessDownloadOptions {
+                output_name: output_filename,
+                start_byte: start_byte.unwrap_or(0),
+                end_byte,
+                exact_range: false,
+                verify: false,
+            },
             LowDiskInteractionFunctions {
                 client: client.clone(),
                 api_key: "".to_string(),
                 should_use_counter_nonce: false,
+                chunk_cache: Some(self.chunk_cache.clone()),
             },
         )
         .await
@@ -117,26 +162,31 @@ impl FilenSDK {
             std::fs::create_dir(&tmp_dir).unwrap();
         }
 
-        let start_byte = start_byte.unwrap_or(0);
-        let end_byte = end_byte.unwrap_or(file_size);
-
         let output_dir = std::path::Path::new(&output_dir);
 
         self.orderless_file_download(
-            &uuid,
-            &region,
-            &bucket,
-            key,
-            &output_dir,
-            output_filename,
-            file_size,
-            start_byte,
-            end_byte,
+            ChunkSource {
+                uuid,
+                region,
+                bucket,
+                key,
+                size: file_size,
+                hash: None,
+            },
+            output_dir,
+            OrderlessDownloadOptions {
+                output_name: output_filename,
+                start_byte: start_byte.unwrap_or(0),
+                end_byte,
+                exact_range: false,
+                verify: false,
+            },
             LowMemoryInteractionFunctions {
                 client: client.clone(),
                 api_key: "".to_string(),
                 tmp_dir,
                 should_use_counter_nonce: false,
+                chunk_cache: Some(self.chunk_cache.clone()),
             },
         )
         .await
@@ -151,6 +201,15 @@ impl FilenSDK {
     */
 
     /// Convenience function to download a partial file into memory.
+    ///
+    /// `start_byte` is inclusive and `end_byte` exclusive, `None` meaning the start and end of
+    /// the file. Whole chunks covering the range are written, and the returned `FileByteRange`
+    /// is the chunk-aligned range that ended up in `output_file`. Use `download_file_range` to
+    /// get exactly the requested bytes.
+    ///
+    /// When the whole file is downloaded, it is checked against the size and plaintext hash in
+    /// its metadata while it is written. On a mismatch an `IntegrityError` is returned and
+    /// nothing appears at `output_file`.
     pub async fn download_partial_file(
         &self,
         uuid: String,
@@ -162,25 +221,29 @@ impl FilenSDK {
         let metadata = self.file_info(uuid.clone()).await?;
         let (output_dir, file_name) = extract_path_and_filename!(output_file);
 
-        Ok(FilenFileDownloadResult {
-            file_info: metadata.clone(),
-            file_byte_range: self
-                .internal_download_file_low_disk(
-                    metadata.uuid,
-                    metadata.region,
-                    metadata.bucket,
-                    String::from_utf8(metadata.key).unwrap(),
-                    output_dir,
-                    Some(file_name),
-                    metadata.size,
-                    start_byte,
+        let file_byte_range = self
+            .download_verified(
+                &metadata,
+                &output_dir,
+                OrderlessDownloadOptions {
+                    output_name: Some(file_name),
+                    start_byte: start_byte.unwrap_or(0),
                     end_byte,
-                )
-                .await?,
+                    exact_range: false,
+                    verify: true,
+                },
+                None,
+            )
+            .await?;
+
+        Ok(FilenFileDownloadResult {
+            file_info: metadata,
+            file_byte_range,
         })
     }
 
-    /// Convenience function to download a partial file with low memory usage.
+    /// Convenience function to download a partial file with low memory usage. See
+    /// `download_partial_file` for the range semantics and verification.
     pub async fn download_partial_file_low_memory(
         &self,
         uuid: String,
@@ -194,28 +257,71 @@ impl FilenSDK {
         let (output_dir, file_name) = extract_path_and_filename!(output_file);
 
         // Download the partial file
+        let file_byte_range = self
+            .download_verified(
+                &metadata,
+                &output_dir,
+                OrderlessDownloadOptions {
+                    output_name: Some(file_name),
+                    start_byte: start_byte.unwrap_or(0),
+                    end_byte,
+                    exact_range: false,
+                    verify: true,
+                },
+                Some(tmp_dir),
+            )
+            .await?;
+
         Ok(FilenFileDownloadResult {
-            file_info: metadata.clone(),
-            file_byte_range: self
-                .internal_download_file_low_memory(
-                    metadata.uuid,
-                    metadata.region,
-                    metadata.bucket,
-                    String::from_utf8(metadata.key).unwrap(),
-                    output_dir,
-                    Some(file_name),
-                    tmp_dir,
-                    metadata.size,
+            file_info: metadata,
+            file_byte_range,
+        })
+    }
+
+    /// Downloads exactly the bytes `start_byte..end_byte` of a file into `output_file`.
+    ///
+    /// `start_byte` is inclusive and `end_byte` exclusive, so the output holds
+    /// `end_byte - start_byte` bytes. An `end_byte` past the end of the file is clamped to the
+    /// file size. Unlike `download_partial_file`, the first and last chunks are trimmed, and the
+    /// returned `FileByteRange` is exactly the range that was written. Ranges covering the whole
+    /// file are verified like in `download_partial_file`.
+    pub async fn download_file_range(
+        &self,
+        uuid: String,
+        output_file: String,
+        start_byte: u64,
+        end_byte: u64,
+    ) -> Result<FilenFileDownloadResult, crate::error::FilenSDKError> {
+        let metadata = self.file_info(uuid.clone()).await?;
+        let (output_dir, file_name) = extract_path_and_filename!(output_file);
+
+        let file_byte_range = self
+            .download_verified(
+                &metadata,
+                &output_dir,
+                OrderlessDownloadOptions {
+                    output_name: Some(file_name),
                     start_byte,
-                    end_byte,
-                )
-                .await?,
+                    end_byte: Some(end_byte),
+                    exact_range: true,
+                    verify: true,
+                },
+                None,
+            )
+            .await?;
+
+        Ok(FilenFileDownloadResult {
+            file_info: metadata,
+            file_byte_range,
         })
     }
 
     /// Download the file to the specified output_dir all in chunks. The output will have a folder
     /// with a bunch of files that are the chunks of the original file. A chunk is CHUNK_SIZE bytes
     /// and the files will be titled with their chunk index.
+    ///
+    /// When every chunk is downloaded, they are checked against the size and plaintext hash in
+    /// the file metadata, and an `IntegrityError` is returned on a mismatch.
     pub async fn download_file_chunked(
         &self,
         uuid: String,
@@ -227,20 +333,20 @@ impl FilenSDK {
         let metadata = self.file_info(uuid.clone()).await?;
 
         Ok(FilenFileDownloadResult {
-            file_info: metadata.clone(),
             file_byte_range: self
-                .internal_download_file_low_disk(
-                    metadata.uuid,
-                    metadata.region,
-                    metadata.bucket,
-                    String::from_utf8(metadata.key).unwrap(),
-                    output_dir,
+                .download_verified(
+                    &metadata,
+                    &output_dir,
+                    OrderlessDownloadOptions {
+                        start_byte: start_byte.unwrap_or(0),
+                        end_byte,
+                        verify: true,
+                        ..Default::default()
+                    },
                     None,
-                    metadata.size,
-                    start_byte,
-                    end_byte,
                 )
                 .await?,
+            file_info: metadata,
         })
     }
 
@@ -258,21 +364,20 @@ impl FilenSDK {
         let metadata = self.file_info(uuid.clone()).await?;
 
         Ok(FilenFileDownloadResult {
-            file_info: metadata.clone(),
             file_byte_range: self
-                .internal_download_file_low_memory(
-                    metadata.uuid,
-                    metadata.region,
-                    metadata.bucket,
-                    String::from_utf8(metadata.key).unwrap(),
-                    output_dir,
-                    None,
-                    tmp_dir,
-                    metadata.size,
-                    start_byte,
-                    end_byte,
+                .download_verified(
+                    &metadata,
+                    &output_dir,
+                    OrderlessDownloadOptions {
+                        start_byte: start_byte.unwrap_or(0),
+                        end_byte,
+                        verify: true,
+                        ..Default::default()
+                    },
+                    Some(tmp_dir),
                 )
                 .await?,
+            file_info: metadata,
         })
     }
 
@@ -285,6 +390,9 @@ impl FilenSDK {
     ///
     /// # Returns
     /// A `FileByteRange` struct with the start and end byte of the downloaded file.
+    ///
+    /// If the download fails, calling this function again resumes it from the
+    /// `<output_file>.filenpart` manifest, as long as the remote file has not changed.
     pub async fn download_file(
         &self,
         uuid: String,
@@ -305,4 +413,157 @@ impl FilenSDK {
         self.download_partial_file_low_memory(uuid, output_file, tmp_dir, None, None)
             .await
     }
+
+    /// Downloads and decrypts the file `uuid` without writing it anywhere, and checks it against
+    /// the size and plaintext hash in its metadata. A mismatch is an `IntegrityError`.
+    pub async fn verify_file(
+        &self,
+        uuid: String,
+    ) -> Result<FilenFileVerification, crate::error::FilenSDKError> {
+        let metadata = self.file_info(uuid).await?;
+        let mut stream = pin!(self.file_plaintext_stream(&metadata)?);
+
+        let mut hasher = PlaintextHasher::new();
+        while let Some(data) = stream.try_next().await? {
+            hasher.update(&data);
+        }
+
+        Ok(FilenFileVerification {
+            hash_verified: hasher.verify(&metadata)?,
+            file_info: metadata,
+        })
+    }
+
+    /// Downloads a file into a foreign `FilenDownloadWriter`, see `download_to_writer`. The
+    /// writer is called from a blocking thread.
+    pub async fn download_to_callback(
+        &self,
+        uuid: String,
+        writer: Arc<dyn FilenDownloadWriter>,
+    ) -> Result<FilenFileDetailed, crate::error::FilenSDKError> {
+        let (file_info, _writer) = self
+            .download_to_std_writer(uuid, ForeignWriter(writer))
+            .await?;
+        Ok(file_info)
+    }
+}
+
+impl FilenSDK {
+    /// Downloads a file into any `AsyncWrite`, such as a compressor, a hasher or a socket.
+    ///
+    /// Chunks are downloaded in parallel but written strictly in order, with a bounded number of
+    /// chunks buffered ahead of the writer (see `ordered_download_stream`). The writer is flushed
+    /// but not shut down once the whole file has been written.
+    ///
+    /// The plaintext is hashed while writing and checked against the file metadata at the end,
+    /// so an `IntegrityError` is only returned after everything was written.
+    pub async fn download_to_writer<W>(
+        &self,
+        uuid: String,
+        writer: &mut W,
+    ) -> Result<FilenFileDetailed, FilenSDKError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let metadata = self.file_info(uuid).await?;
+        let mut stream = pin!(verified_stream(metadata.clone(), self.file_plaintext_stream(&metadata)?));
+
+        while let Some(data) = stream.try_next().await? {
+            writer.write_all(&data).await?;
+        }
+        writer.flush().await?;
+
+        Ok(metadata)
+    }
+
+    /// `download_to_writer` for blocking `std::io::Write` sinks. Writes happen on a blocking
+    /// thread, so the writer is moved in and handed back once the download has finished.
+    pub async fn download_to_std_writer<W>(
+        &self,
+        uuid: String,
+        writer: W,
+    ) -> Result<(FilenFileDetailed, W), FilenSDKError>
+    where
+        W: Write + Send + 'static,
+    {
+        let metadata = self.file_info(uuid).await?;
+        let mut stream = pin!(verified_stream(metadata.clone(), self.file_plaintext_stream(&metadata)?));
+
+        let mut writer = writer;
+        while let Some(data) = stream.try_next().await? {
+            writer = tokio::task::spawn_blocking(move || writer.write_all(&data).map(|_| writer))
+                .await??;
+        }
+        let writer = tokio::task::spawn_blocking(move || writer.flush().map(|_| writer)).await??;
+
+        Ok((metadata, writer))
+    }
+
+    /// `orderless_file_download` of `metadata` into `output_dir`, checked against the metadata
+    /// as set in `options`. Chunks go through `tmp_dir` with the low memory functions if given.
+    async fn download_verified(
+        &self,
+        metadata: &FilenFileDetailed,
+        output_dir: &str,
+        options: OrderlessDownloadOptions,
+        tmp_dir: Option<String>,
+    ) -> Result<FileByteRange, FilenSDKError> {
+        let source = ChunkSource::try_from(metadata)?;
+        let output_dir = std::path::Path::new(output_dir);
+
+        let (start_byte, end_byte) = match tmp_dir {
+            Some(tmp_dir) => {
+                tokio::fs::create_dir_all(&tmp_dir).await?;
+                self.orderless_file_download(
+                    source,
+                    output_dir,
+                    options,
+                    LowMemoryInteractionFunctions {
+                        client: self.client.clone(),
+                        api_key: "".to_string(),
+                        tmp_dir,
+                        should_use_counter_nonce: false,
+                        chunk_cache: Some(self.chunk_cache.clone()),
+                    },
+                )
+                .await?
+            }
+            None => {
+                self.orderless_file_download(
+                    source,
+                    output_dir,
+                    options,
+                    LowDiskInteractionFunctions {
+                        client: self.client.clone(),
+                        api_key: "".to_string(),
+                        should_use_counter_nonce: false,
+                        chunk_cache: Some(self.chunk_cache.clone()),
+                    },
+                )
+                .await?
+            }
+        };
+
+        Ok(FileByteRange {
+            start_byte,
+            end_byte,
+        })
+    }
+
+    fn file_plaintext_stream(
+        &self,
+        metadata: &FilenFileDetailed,
+    ) -> Result<impl Stream<Item = Result<Bytes, FilenSDKError>>, FilenSDKError> {
+        Ok(self.ordered_download_stream(
+            ChunkSource::try_from(metadata)?,
+            0,
+            metadata.size,
+            LowDiskInteractionFunctions {
+                client: self.client.clone(),
+                api_key: "".to_string(),
+                should_use_counter_nonce: false,
+                chunk_cache: Some(self.chunk_cache.clone()),
+            },
+        ))
+    }
 }
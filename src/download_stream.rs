@@ -1,19 +1,29 @@
-use std::{pin::Pin, sync::{Arc}};
+use std::{pin::Pin, sync::Arc};
 
 use futures::{lock::Mutex, TryStreamExt};
 use futures_core::Stream;
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 
-use crate::{error::FilenSDKError, FilenSDK};
+use crate::{error::FilenSDKError, file::FilenFileDetailed, httpclient::ChunkSource, FilenSDK};
 
+type PlaintextStream = Pin<Box<dyn Stream<Item = Result<Bytes, FilenSDKError>> + Send + Sync>>;
+
+/// Sequential reader over `start_byte..end_byte` of a remote file for foreign callers.
+///
+/// Chunks are downloaded ahead of the reader through `read_ahead_download_stream`. Seeking
+/// outside of the buffered chunk drops the read-ahead and restarts it at the new position, and
+/// `close` cancels it for good.
 #[derive(uniffi::Object)]
 pub struct FilenDownloadStream {
-    internal_stream: Arc<futures::lock::Mutex<Pin<Box<dyn Stream<Item = Result<Bytes, FilenSDKError>> + Send + Sync>>>>,
+    state: Mutex<StreamState>,
+    source: ChunkSource,
+    end_byte: u64,
     filen_sdk: Arc<FilenSDK>,
 }
 
 #[uniffi::export]
 impl FilenDownloadStream {
+    /// Streams the file from `start_byte` to its end.
     #[uniffi::constructor]
     pub fn new(
         size: u64,
@@ -24,17 +34,28 @@ impl FilenDownloadStream {
         uuid: String,
         key: String,
     ) -> Self {
-        Self {
-            internal_stream: Arc::new(Mutex::new(Box::pin(filen_sdk.read_ahead_download_stream(
-                size,
-                start_byte,
-                region,
-                bucket,
-                uuid,
-                key,
-            )))),
-            filen_sdk,
-        }
+        let source = ChunkSource {
+            uuid,
+            region,
+            bucket,
+            key,
+            size,
+            hash: None,
+        };
+
+        Self::with_source(source, filen_sdk, start_byte, None)
+    }
+
+    /// Streams `start_byte..end_byte` of `file`, `end_byte` defaults to (and is clamped to) the
+    /// file size.
+    #[uniffi::constructor]
+    pub fn new_from_file(
+        file: FilenFileDetailed,
+        filen_sdk: Arc<FilenSDK>,
+        start_byte: u64,
+        end_byte: Option<u64>,
+    ) -> Result<Self, FilenSDKError> {
+        Ok(Self::with_source(ChunkSource::try_from(&file)?, filen_sdk, start_byte, end_byte))
     }
 
     #[uniffi::constructor]
@@ -42,35 +63,302 @@ impl FilenDownloadStream {
         uuid: &str,
         filen_sdk: Arc<FilenSDK>,
         start_byte: u64,
+        end_byte: Option<u64>,
     ) -> Result<Self, FilenSDKError> {
         let info = filen_sdk.file_info(uuid.to_owned()).await?;
 
-        Ok(Self::new(
-            info.size,
-            start_byte,
-            filen_sdk,
-            info.region,
-            info.bucket,
-            uuid.to_string(),
-            String::from_utf8(info.key).unwrap(),
-        ))
+        Self::new_from_file(info, filen_sdk, start_byte, end_byte)
+    }
+
+    /// Offset of the next byte returned by `next` or `read`
+    pub fn position(&self) -> u64 {
+        self.block_on(async { self.state.lock().await.position })
     }
 
     #[uniffi::method(name = "next")]
-    pub fn next_blocking(&self) -> Result<Vec<u8>, FilenSDKError> {
-        self.filen_sdk.tokio_runtime.lock().unwrap().as_ref().unwrap().block_on(self.next())
+    pub fn next_blocking(&self) -> Result<Option<Vec<u8>>, FilenSDKError> {
+        self.block_on(self.next())
+    }
+
+    #[uniffi::method(name = "read")]
+    pub fn read_blocking(&self, max_len: u64) -> Result<Option<Vec<u8>>, FilenSDKError> {
+        self.block_on(self.read(max_len))
+    }
+
+    #[uniffi::method(name = "seek")]
+    pub fn seek_blocking(&self, position: u64) -> Result<(), FilenSDKError> {
+        self.block_on(self.seek(position))
+    }
+
+    #[uniffi::method(name = "close")]
+    pub fn close_blocking(&self) {
+        self.block_on(self.close())
     }
 }
 
 impl FilenDownloadStream {
-    pub async fn next(&self) -> Result<Vec<u8>, FilenSDKError> {
-        let mut stream = self.internal_stream.lock().await;
-        let mut stream = stream.as_mut();
-        let next = stream.try_next().await;
-        if let Some(next) = next?.map(|b| b.to_vec()) {
-            Ok(next)
+    fn with_source(
+        source: ChunkSource,
+        filen_sdk: Arc<FilenSDK>,
+        start_byte: u64,
+        end_byte: Option<u64>,
+    ) -> Self {
+        let end_byte = end_byte.map_or(source.size, |end_byte| std::cmp::min(end_byte, source.size));
+
+        Self {
+            state: Mutex::new(StreamState::new(start_byte, end_byte)),
+            source,
+            end_byte,
+            filen_sdk,
+        }
+    }
+
+    /// Returns the rest of the current chunk, or `None` once `end_byte` was reached.
+    pub async fn next(&self) -> Result<Option<Vec<u8>>, FilenSDKError> {
+        self.read_bytes(usize::MAX).await
+    }
+
+    /// Returns up to `max_len` bytes, or `None` once `end_byte` was reached. Reads never span
+    /// chunks, so fewer bytes may be returned before the end.
+    pub async fn read(&self, max_len: u64) -> Result<Option<Vec<u8>>, FilenSDKError> {
+        self.read_bytes(usize::try_from(max_len).unwrap_or(usize::MAX)).await
+    }
+
+    /// Moves the stream to `position`, positions past `end_byte` are at the end. Seeking within
+    /// the buffered chunk keeps the read-ahead running.
+    pub async fn seek(&self, position: u64) -> Result<(), FilenSDKError> {
+        self.state.lock().await.seek(position)
+    }
+
+    /// Cancels outstanding read-ahead downloads. Reading or seeking afterwards fails with
+    /// `StreamEnded`.
+    pub async fn close(&self) {
+        self.state.lock().await.close()
+    }
+
+    async fn read_bytes(&self, max_len: usize) -> Result<Option<Vec<u8>>, FilenSDKError> {
+        let mut state = self.state.lock().await;
+        let data = state
+            .read(max_len, |position| {
+                Box::pin(self.filen_sdk.read_ahead_download_stream(
+                    self.source.clone(),
+                    position,
+                    self.end_byte,
+                ))
+            })
+            .await?;
+
+        Ok(data.map(|data| data.to_vec()))
+    }
+
+    fn block_on<T>(&self, future: impl std::future::Future<Output = T>) -> T {
+        self.filen_sdk.tokio_runtime.lock().unwrap().as_ref().unwrap().block_on(future)
+    }
+}
+
+struct StreamState {
+    /// Read-ahead stream starting at `position + buffer.len()`, opened on the first read after
+    /// construction or a seek
+    stream: Option<PlaintextStream>,
+    /// Plaintext received from the stream but not returned yet
+    buffer: Bytes,
+    position: u64,
+    end_byte: u64,
+    closed: bool,
+}
+
+impl StreamState {
+    fn new(position: u64, end_byte: u64) -> Self {
+        Self {
+            stream: None,
+            buffer: Bytes::new(),
+            position,
+            end_byte,
+            closed: false,
+        }
+    }
+
+    async fn read(
+        &mut self,
+        max_len: usize,
+        open: impl FnOnce(u64) -> PlaintextStream,
+    ) -> Result<Option<Bytes>, FilenSDKError> {
+        if self.closed {
+            return Err(FilenSDKError::StreamEnded);
+        }
+
+        if self.buffer.is_empty() {
+            if self.position >= self.end_byte {
+                return Ok(None);
+            }
+
+            let position = self.position;
+            let stream = self.stream.get_or_insert_with(|| open(position));
+            while self.buffer.is_empty() {
+                match stream.try_next().await {
+                    Ok(Some(data)) => self.buffer = data,
+                    // The stream stops at a failed chunk, the next read reopens it
+                    Err(err) => {
+                        self.stream = None;
+                        return Err(err);
+                    }
+                    Ok(None) => {
+                        self.stream = None;
+                        return Err(FilenSDKError::DownloadError {
+                            err_str: format!(
+                                "Download stream ended at byte {} of {}",
+                                position, self.end_byte
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        let data = self.buffer.split_to(std::cmp::min(max_len, self.buffer.len()));
+        self.position += data.len() as u64;
+        Ok(Some(data))
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), FilenSDKError> {
+        if self.closed {
+            return Err(FilenSDKError::StreamEnded);
+        }
+
+        let position = std::cmp::min(position, self.end_byte);
+        let buffered = self.position..=self.position + self.buffer.len() as u64;
+        if buffered.contains(&position) {
+            self.buffer.advance((position - self.position) as usize);
         } else {
-            Err(FilenSDKError::StreamEnded)
+            // Dropping the stream aborts its read-ahead tasks
+            self.stream = None;
+            self.buffer.clear();
+        }
+        self.position = position;
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+        self.buffer.clear();
+        self.closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+    use crate::CHUNK_SIZE;
+
+    fn plaintext(len: usize) -> Bytes {
+        (0..len).map(|i| (i * 13 % 241) as u8).collect()
+    }
+
+    /// Mimics `read_ahead_download_stream` over `data`, recording where streams are opened
+    fn opener<'a>(
+        data: &'a Bytes,
+        end_byte: u64,
+        opened: &'a StdMutex<Vec<u64>>,
+    ) -> impl Fn(u64) -> PlaintextStream + 'a {
+        move |position| {
+            opened.lock().unwrap().push(position);
+
+            let chunks: Vec<Result<Bytes, FilenSDKError>> = (position / CHUNK_SIZE as u64
+                ..end_byte.div_ceil(CHUNK_SIZE as u64))
+                .map(|i| {
+                    let start = std::cmp::max(i * CHUNK_SIZE as u64, position) as usize;
+                    let end = std::cmp::min((i + 1) * CHUNK_SIZE as u64, end_byte) as usize;
+                    Ok(data.slice(start..end))
+                })
+                .collect();
+            Box::pin(futures::stream::iter(chunks))
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_read_until_end_byte() {
+        let data = plaintext(CHUNK_SIZE * 2 + 10);
+        let end_byte = CHUNK_SIZE as u64 + 5;
+        let opened = StdMutex::new(Vec::new());
+        let open = opener(&data, end_byte, &opened);
+        let mut state = StreamState::new(10, end_byte);
+
+        let first = state.read(100, &open).await.unwrap().unwrap();
+        assert_eq!(first, data.slice(10..110));
+
+        let mut rest = Vec::new();
+        while let Some(chunk) = state.read(usize::MAX, &open).await.unwrap() {
+            rest.extend_from_slice(&chunk);
+        }
+        assert_eq!(rest, data.slice(110..end_byte as usize));
+        assert_eq!(state.position, end_byte);
+        assert!(state.read(1, &open).await.unwrap().is_none());
+        assert_eq!(*opened.lock().unwrap(), [10]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_chunk_is_an_error() {
+        let data = plaintext(CHUNK_SIZE * 2);
+        let end_byte = data.len() as u64;
+        let opened = StdMutex::new(Vec::new());
+        let open = opener(&data, end_byte, &opened);
+        let failing = |position: u64| -> PlaintextStream {
+            opened.lock().unwrap().push(position);
+            Box::pin(futures::stream::iter(vec![
+                Ok(data.slice(..CHUNK_SIZE)),
+                Err(FilenSDKError::DownloadError {
+                    err_str: "chunk 1".to_string(),
+                }),
+            ]))
+        };
+        let mut state = StreamState::new(0, end_byte);
+
+        assert_eq!(state.read(usize::MAX, failing).await.unwrap().unwrap(), data.slice(..CHUNK_SIZE));
+        assert!(matches!(
+            state.read(usize::MAX, failing).await,
+            Err(FilenSDKError::DownloadError { .. })
+        ));
+
+        // The failed stream is dropped and the next read retries from the same position
+        let chunk = state.read(usize::MAX, &open).await.unwrap().unwrap();
+        assert_eq!(chunk, data.slice(CHUNK_SIZE..));
+        assert_eq!(*opened.lock().unwrap(), [0, CHUNK_SIZE as u64]);
+    }
+
+    #[tokio::test]
+    async fn test_seek_and_close() {
+        let data = plaintext(CHUNK_SIZE * 3);
+        let end_byte = data.len() as u64;
+        let opened = StdMutex::new(Vec::new());
+        let open = opener(&data, end_byte, &opened);
+        let mut state = StreamState::new(0, end_byte);
+
+        state.read(10, &open).await.unwrap();
+
+        // Within the buffered chunk the stream is kept
+        state.seek(1000).unwrap();
+        assert_eq!(state.read(10, &open).await.unwrap().unwrap(), data.slice(1000..1010));
+        assert_eq!(*opened.lock().unwrap(), [0]);
+
+        // Elsewhere it is reopened at the new position
+        state.seek(CHUNK_SIZE as u64 * 2 + 7).unwrap();
+        let chunk = state.read(usize::MAX, &open).await.unwrap().unwrap();
+        assert_eq!(chunk, data.slice(CHUNK_SIZE * 2 + 7..));
+        assert_eq!(*opened.lock().unwrap(), [0, CHUNK_SIZE as u64 * 2 + 7]);
+
+        state.seek(5).unwrap();
+        assert_eq!(state.read(5, &open).await.unwrap().unwrap(), data.slice(5..10));
+
+        state.seek(end_byte + 100).unwrap();
+        assert_eq!(state.position, end_byte);
+        assert!(state.read(10, &open).await.unwrap().is_none());
+
+        state.close();
+        assert!(state.stream.is_none());
+        assert!(matches!(state.read(10, &open).await, Err(FilenSDKError::StreamEnded)));
+        assert!(matches!(state.seek(0), Err(FilenSDKError::StreamEnded)));
+    }
+}
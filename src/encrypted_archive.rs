@@ -0,0 +1,285 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{
+    crypto::{
+        file_decrypt::decrypt_v2_bytes,
+        key_wrap::{unwrap_key, wrap_key, WrappedKey},
+        CHUNK_OVERHEAD,
+    },
+    error::FilenSDKError,
+    file::FilenFileDetailed,
+    filensdk::MAX_DOWNLOAD_THREADS,
+    httpclient::FsURL,
+    mod_private::{integrity::PlaintextHasher, net_interaction::LowDiskInteractionFunctions},
+    FilenSDK, CHUNK_SIZE,
+};
+
+/// Name of the manifest written next to the chunks by `download_encrypted_chunks`
+pub const ENCRYPTED_MANIFEST_NAME: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+struct EncryptedChunksManifest {
+    version: u32,
+    uuid: String,
+    name: String,
+    size: u64,
+    mime: String,
+    last_modified: Option<i64>,
+    hash: Option<String>,
+    chunks: u64,
+    key: ManifestKey,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ManifestKey {
+    Plain { key: String },
+    Passphrase { wrapped: WrappedKey },
+}
+
+/// Outcome of `decrypt_offline`
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct FilenOfflineDecryption {
+    pub name: String,
+    pub size: u64,
+    /// Whether the plaintext was checked against the hash stored in the manifest
+    pub hash_verified: bool,
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Mirrors the file `uuid` as stored by Filen: every encrypted chunk is written unchanged to
+    /// `output_dir/<chunk index>`, next to a `manifest.json` holding the file metadata and key.
+    /// With a `passphrase`, the key is wrapped with it, otherwise it is stored in the clear.
+    ///
+    /// The manifest is written last, so its presence marks a complete copy. Chunks already in
+    /// `output_dir` with the expected size are not downloaded again. Use `decrypt_offline` to
+    /// decrypt the copy.
+    pub async fn download_encrypted_chunks(
+        &self,
+        uuid: String,
+        output_dir: String,
+        passphrase: Option<String>,
+    ) -> Result<FilenFileDetailed, FilenSDKError> {
+        let file = self.file_info(uuid).await?;
+        let output_dir = PathBuf::from(output_dir);
+        tokio::fs::create_dir_all(&output_dir).await?;
+
+        let key = String::from_utf8(file.key.clone())?;
+        let chunks = file.size.div_ceil(CHUNK_SIZE as u64);
+        let download_funcs = LowDiskInteractionFunctions {
+            client: self.client.clone(),
+            api_key: "".to_string(),
+            should_use_counter_nonce: false,
+            chunk_cache: Some(self.chunk_cache.clone()),
+        };
+
+        futures::stream::iter(0..chunks)
+            .map(|i| {
+                let path = output_dir.join(i.to_string());
+                let download_funcs = download_funcs.clone();
+                let file = &file;
+                async move {
+                    let expected_len = encrypted_chunk_len(file.size, i);
+                    if tokio::fs::metadata(&path)
+                        .await
+                        .is_ok_and(|metadata| metadata.len() == expected_len)
+                    {
+                        return Ok(());
+                    }
+
+                    let link = FsURL::Egest(file.region.clone(), file.bucket.clone(), file.uuid.clone(), i);
+                    let data = {
+                        let _permit = self.download_semaphore.acquire().await.unwrap();
+                        Self::attempt_download_chunk_task(link, i, &download_funcs).await?
+                    };
+                    if data.len() as u64 != expected_len {
+                        return Err(FilenSDKError::DownloadError {
+                            err_str: format!(
+                                "Chunk {} has {} bytes, expected {}",
+                                i,
+                                data.len(),
+                                expected_len
+                            ),
+                        });
+                    }
+
+                    // Written under a temporary name, so existing chunks are always complete
+                    let tmp_path = path.with_extension("tmp");
+                    tokio::fs::write(&tmp_path, &data).await?;
+                    tokio::fs::rename(&tmp_path, &path).await?;
+                    Ok::<(), FilenSDKError>(())
+                }
+            })
+            .buffer_unordered(MAX_DOWNLOAD_THREADS)
+            .try_collect::<()>()
+            .await?;
+
+        let manifest = EncryptedChunksManifest {
+            version: MANIFEST_VERSION,
+            uuid: file.uuid.clone(),
+            name: file.name.clone(),
+            size: file.size,
+            mime: file.mime.clone(),
+            last_modified: file.last_modified,
+            hash: file.hash.clone(),
+            chunks,
+            key: match passphrase {
+                Some(passphrase) => ManifestKey::Passphrase {
+                    wrapped: wrap_key(key.as_bytes(), &passphrase)?,
+                },
+                None => ManifestKey::Plain { key },
+            },
+        };
+        tokio::fs::write(
+            output_dir.join(ENCRYPTED_MANIFEST_NAME),
+            serde_json::to_vec_pretty(&manifest)?,
+        )
+        .await?;
+
+        Ok(file)
+    }
+}
+
+/// Decrypts a copy made by `download_encrypted_chunks` into `output_file`, without network
+/// access or credentials. `manifest` is the path of its `manifest.json`, the chunks are read
+/// from the same folder. `passphrase` is required if the key was wrapped with one.
+///
+/// The plaintext is checked against the size and hash in the manifest, and a mismatch is an
+/// `IntegrityError`. This function blocks, call it from a blocking thread in async code.
+#[uniffi::export]
+pub fn decrypt_offline(
+    manifest: String,
+    output_file: String,
+    passphrase: Option<String>,
+) -> Result<FilenOfflineDecryption, FilenSDKError> {
+    let manifest_path = PathBuf::from(manifest);
+    let manifest: EncryptedChunksManifest =
+        serde_json::from_slice(&std::fs::read(&manifest_path)?)?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(FilenSDKError::UnknownError {
+            err_str: format!("Unsupported manifest version {}", manifest.version),
+        });
+    }
+
+    let key = match (&manifest.key, passphrase) {
+        (ManifestKey::Plain { key }, _) => key.as_bytes().to_vec(),
+        (ManifestKey::Passphrase { wrapped }, Some(passphrase)) => unwrap_key(wrapped, &passphrase)
+            .map_err(|_| FilenSDKError::InvalidPassphrase)?,
+        (ManifestKey::Passphrase { .. }, None) => return Err(FilenSDKError::InvalidPassphrase),
+    };
+
+    let chunk_dir = manifest_path.parent().unwrap_or(Path::new("."));
+    let mut output = std::io::BufWriter::new(std::fs::File::create(&output_file)?);
+    let mut hasher = PlaintextHasher::new();
+
+    for i in 0..manifest.chunks {
+        let mut data = BytesMut::from(Bytes::from(std::fs::read(chunk_dir.join(i.to_string()))?));
+
+        let plaintext = decrypt_v2_bytes(&mut data, &key).map_err(|e| {
+            FilenSDKError::DownloadError {
+                err_str: format!("Error decrypting chunk {}: {}", i, e),
+            }
+        })?;
+        hasher.update(&plaintext);
+        output.write_all(&plaintext)?;
+    }
+    output.flush()?;
+
+    Ok(FilenOfflineDecryption {
+        hash_verified: hasher.verify_parts(&manifest.uuid, manifest.size, manifest.hash.as_deref())?,
+        name: manifest.name,
+        size: manifest.size,
+    })
+}
+
+fn encrypted_chunk_len(size: u64, i: u64) -> u64 {
+    let plaintext = std::cmp::min(CHUNK_SIZE as u64, size - i * CHUNK_SIZE as u64);
+    plaintext + CHUNK_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::digest::{digest, SHA512};
+
+    use super::*;
+    use crate::crypto::{file_encrypt::encrypt_v2_bytes, generate_rand_key};
+
+    /// Writes what `download_encrypted_chunks` would for `plaintext`
+    fn write_copy(dir: &Path, plaintext: &[u8], key: ManifestKey, raw_key: &[u8; 32]) -> String {
+        std::fs::create_dir_all(dir).unwrap();
+
+        let chunks = plaintext.chunks(CHUNK_SIZE).enumerate();
+        for (i, chunk) in chunks.clone() {
+            let (data, _) = encrypt_v2_bytes(chunk, raw_key, i, false).unwrap();
+            assert_eq!(data.len() as u64, encrypted_chunk_len(plaintext.len() as u64, i as u64));
+            std::fs::write(dir.join(i.to_string()), data).unwrap();
+        }
+
+        let manifest = EncryptedChunksManifest {
+            version: MANIFEST_VERSION,
+            uuid: "uuid".to_string(),
+            name: "file.bin".to_string(),
+            size: plaintext.len() as u64,
+            mime: "application/octet-stream".to_string(),
+            last_modified: None,
+            hash: Some(hex::encode(digest(&SHA512, plaintext))),
+            chunks: chunks.count() as u64,
+            key,
+        };
+        let path = dir.join(ENCRYPTED_MANIFEST_NAME);
+        std::fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_decrypt_offline() {
+        let dir = std::env::temp_dir().join(format!("encrypted-{}", uuid::Uuid::new_v4()));
+        let plaintext: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 239) as u8).collect();
+        let key = generate_rand_key().unwrap();
+        let output = dir.join("output.bin").to_string_lossy().to_string();
+
+        let manifest = write_copy(
+            &dir,
+            &plaintext,
+            ManifestKey::Plain { key: String::from_utf8(key.to_vec()).unwrap() },
+            &key,
+        );
+        let result = decrypt_offline(manifest, output.clone(), None).unwrap();
+        assert!(result.hash_verified);
+        assert_eq!(std::fs::read(&output).unwrap(), plaintext);
+
+        // A passphrase wrapped key needs the right passphrase
+        let wrapped = ManifestKey::Passphrase {
+            wrapped: wrap_key(&key, "passphrase").unwrap(),
+        };
+        let manifest = write_copy(&dir, &plaintext, wrapped, &key);
+        assert!(matches!(
+            decrypt_offline(manifest.clone(), output.clone(), None),
+            Err(FilenSDKError::InvalidPassphrase)
+        ));
+        assert!(matches!(
+            decrypt_offline(manifest.clone(), output.clone(), Some("wrong".to_string())),
+            Err(FilenSDKError::InvalidPassphrase)
+        ));
+        decrypt_offline(manifest.clone(), output.clone(), Some("passphrase".to_string())).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plaintext);
+
+        // Chunks carry their own iv, so swapped chunks decrypt fine and only the hash catches them
+        std::fs::rename(dir.join("0"), dir.join("tmp")).unwrap();
+        std::fs::rename(dir.join("1"), dir.join("0")).unwrap();
+        std::fs::rename(dir.join("tmp"), dir.join("1")).unwrap();
+        let result = decrypt_offline(manifest, output, Some("passphrase".to_string()));
+        assert!(matches!(result, Err(FilenSDKError::IntegrityError { .. })));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -26,6 +26,9 @@ pub enum FilenSDKError {
     #[error("File does not exist: {file}")]
     FileDoesNotExist { file: String },
 
+    #[error("File already exists: {name} ({uuid})")]
+    FileAlreadyExists { name: String, uuid: String },
+
     #[error("Error encrypting file: {err_str}")]
     EncryptionError { err_str: String },
 
@@ -44,6 +47,29 @@ pub enum FilenSDKError {
     #[error("Error creating path: {path}")]
     PathIsDirectory { path: String },
 
+    #[error("Invalid byte range {start_byte}..{end_byte} for a file of {file_size} bytes")]
+    InvalidByteRange {
+        start_byte: u64,
+        end_byte: u64,
+        file_size: u64,
+    },
+
+    #[error("Invalid pattern {pattern}: {err_str}")]
+    InvalidPattern { pattern: String, err_str: String },
+
+    #[error("Integrity check of {uuid} failed, expected {expected} but got {actual}")]
+    IntegrityError {
+        uuid: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Upload source changed since the upload started: {file}")]
+    UploadSourceChanged { file: String },
+
+    #[error("Missing or wrong passphrase")]
+    InvalidPassphrase,
+
     #[error("Stream Ended")]
     StreamEnded,
 
@@ -54,6 +80,18 @@ pub enum FilenSDKError {
     UnknownError { err_str: String },
 }
 
+impl FilenSDKError {
+    /// Whether the API reported that the requested item does not exist, which is also what file
+    /// endpoints report for folder uuids and the other way around
+    pub(crate) fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            FilenSDKError::APIError { code: Some(code), .. }
+                if code == "not_found" || code.ends_with("_not_found")
+        )
+    }
+}
+
 impl From<CryptoError> for FilenSDKError {
     fn from(err: CryptoError) -> Self {
         FilenSDKError::EncryptionError { err_str: err.to_string() }
@@ -84,6 +122,18 @@ impl From<std::io::Error> for FilenSDKError {
     }
 }
 
+impl From<tokio::task::JoinError> for FilenSDKError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        FilenSDKError::UnknownError { err_str: err.to_string() }
+    }
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for FilenSDKError {
+    fn from(err: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        FilenSDKError::UnknownError { err_str: err.reason }
+    }
+}
+
 impl From<reqwest::Error> for FilenSDKError {
     fn from(err: reqwest::Error) -> Self {
         FilenSDKError::ReqwestError { err_str: err.to_string() }
@@ -94,4 +144,22 @@ impl From<Box<dyn std::error::Error>> for FilenSDKError {
     fn from(err: Box<dyn std::error::Error>) -> Self {
         FilenSDKError::UnknownStandardError { err_str: err.to_string() }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_not_found() {
+        let api_error = |code: Option<&str>| FilenSDKError::APIError {
+            message: String::new(),
+            code: code.map(str::to_string),
+        };
+
+        assert!(api_error(Some("file_not_found")).is_not_found());
+        assert!(api_error(Some("folder_not_found")).is_not_found());
+        assert!(!api_error(Some("api_key_not_found_or_invalid")).is_not_found());
+        assert!(!api_error(None).is_not_found());
+        assert!(!FilenSDKError::ReqwestError { err_str: String::new() }.is_not_found());
+    }
+}
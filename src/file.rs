@@ -1,6 +1,6 @@
 use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
 
-use crate::{httpclient::make_request, requests::fs::{FileInfoBody, FileMetadata}, responses::auth::AuthVersion, FilenSDK};
+use crate::{httpclient::{make_request, make_request_without_data}, requests::fs::{ExistsBody, FileInfoBody, FileMetadata, TrashBody}, responses::{auth::AuthVersion, fs::ExistsResponse}, FilenSDK};
 
 #[derive(uniffi::Record, Debug, Clone)]
 pub struct FilenFileDetailed {
@@ -12,6 +12,8 @@ pub struct FilenFileDetailed {
     pub mime: String,
     pub key: Vec<u8>,
     pub last_modified: Option<i64>,
+    /// SHA-512 hex digest of the plaintext, if the uploading client stored one
+    pub hash: Option<String>,
     pub parent: String,
     pub versioned: Option<bool>,
     pub trash: bool,
@@ -36,6 +38,41 @@ impl FilenSDK {
         ).await.and_then(|x| self.decrypt_get_response(x))
     }
 
+    /// Checks whether a file named `name` exists directly inside `parent`, returning its uuid if
+    /// it does. Names are compared case-insensitively, the same way Filen hashes them.
+    pub async fn file_exists(
+        &self,
+        parent: String,
+        name: String,
+    ) -> Result<Option<String>, crate::error::FilenSDKError> {
+        let response: ExistsResponse = make_request(
+            crate::httpclient::Endpoints::FileExists,
+            Some(&self.client.clone()),
+            None,
+            Some(&self.api_key()?),
+            Some(ExistsBody {
+                parent,
+                name_hashed: crate::crypto::metadata::hash_fn(&name.to_lowercase())?,
+            })
+        ).await?;
+
+        Ok(response.uuid.filter(|_| response.exists))
+    }
+
+    /// Moves a file to the trash, it can still be restored from there.
+    pub async fn trash_file(
+        &self,
+        uuid: String,
+    ) -> Result<(), crate::error::FilenSDKError> {
+        make_request_without_data(
+            crate::httpclient::Endpoints::FileTrash,
+            Some(&self.client.clone()),
+            None,
+            Some(&self.api_key()?),
+            Some(TrashBody { uuid })
+        ).await
+    }
+
     pub async fn encrypted_file_info(
         &self,
         uuid: String,
@@ -75,6 +112,7 @@ impl FilenSDK {
             mime: metadata.mime.unwrap(),
             key: metadata.key,
             last_modified: metadata.last_modified,
+            hash: metadata.hash,
             parent: response.parent,
             versioned: Some(response.versioned),
             trash: response.trash,
@@ -111,4 +149,25 @@ mod tests {
 
         println!("{:?}", response);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_file_exists() {
+        dotenv::dotenv().ok();
+        let filensdk = FilenSDK::new();
+        filensdk.import_credentials(dotenv::var("TEST_CRED_IMPORT").unwrap());
+
+        let uuid = dotenv::var("TEST_UUID").unwrap();
+        let info = filensdk.file_info_blocking(uuid.clone()).unwrap();
+
+        // Lookups are case-insensitive
+        let existing = filensdk
+            .file_exists_blocking(info.parent.clone(), info.name.to_uppercase())
+            .unwrap();
+        assert_eq!(existing, Some(uuid));
+
+        let missing = filensdk
+            .file_exists_blocking(info.parent, uuid::Uuid::new_v4().to_string())
+            .unwrap();
+        assert_eq!(missing, None);
+    }
+}
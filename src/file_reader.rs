@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncSeek, ReadBuf},
+    sync::Semaphore,
+    task::JoinHandle,
+};
+use tokio_util::io::SyncIoBridge;
+
+use crate::{
+    error::FilenSDKError,
+    filensdk::{READER_CACHE_CHUNKS, READER_PREFETCH_CHUNKS},
+    httpclient::FsURL,
+    mod_private::net_interaction::{FilenNetInteractionFunctions, LowDiskInteractionFunctions},
+    FilenSDK, CHUNK_SIZE,
+};
+
+/// Decrypted chunks ordered from least to most recently used
+struct ChunkCache {
+    capacity: usize,
+    chunks: VecDeque<(u64, Bytes)>,
+}
+
+impl ChunkCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            chunks: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn contains(&self, i: u64) -> bool {
+        self.chunks.iter().any(|(index, _)| *index == i)
+    }
+
+    fn get(&mut self, i: u64) -> Option<Bytes> {
+        let position = self.chunks.iter().position(|(index, _)| *index == i)?;
+        let entry = self.chunks.remove(position)?;
+        let data = entry.1.clone();
+        self.chunks.push_back(entry);
+        Some(data)
+    }
+
+    fn insert(&mut self, i: u64, data: Bytes) {
+        if self.contains(i) {
+            return;
+        }
+
+        if self.chunks.len() >= self.capacity {
+            self.chunks.pop_front();
+        }
+        self.chunks.push_back((i, data));
+    }
+}
+
+/// Random access handle to a remote file, implementing `AsyncRead` and `AsyncSeek`.
+///
+/// Chunks are downloaded and decrypted on demand, and the most recently used
+/// READER_CACHE_CHUNKS chunks are kept in memory. When reads move linearly from one chunk to
+/// the next, the following READER_PREFETCH_CHUNKS chunks are fetched in the background.
+///
+/// Use `into_blocking` for libraries that need `std::io::Read` and `std::io::Seek`.
+pub struct FilenFileReader<F = LowDiskInteractionFunctions> {
+    uuid: String,
+    region: String,
+    bucket: String,
+    key: String,
+    size: u64,
+    download_funcs: F,
+    download_semaphore: Arc<Semaphore>,
+    decrypt_semaphore: Arc<Semaphore>,
+    position: u64,
+    cache: ChunkCache,
+    in_flight: HashMap<u64, JoinHandle<Result<Bytes, FilenSDKError>>>,
+    /// Chunk served by the previous read, used to detect linear access
+    last_chunk: Option<u64>,
+}
+
+impl FilenSDK {
+    /// Opens a random access reader for the file `uuid`, see `FilenFileReader`.
+    pub async fn open_file_reader(&self, uuid: String) -> Result<FilenFileReader, FilenSDKError> {
+        let info = self.file_info(uuid).await?;
+
+        Ok(FilenFileReader::with_download_funcs(
+            self,
+            info.uuid,
+            info.region,
+            info.bucket,
+            String::from_utf8(info.key)?,
+            info.size,
+            LowDiskInteractionFunctions {
+                client: self.client.clone(),
+                api_key: "".to_string(),
+                should_use_counter_nonce: false,
+                chunk_cache: Some(self.chunk_cache.clone()),
+            },
+        ))
+    }
+}
+
+impl<F> FilenFileReader<F>
+where
+    F: FilenNetInteractionFunctions<Bytes> + Unpin,
+{
+    pub(crate) fn with_download_funcs(
+        filen_sdk: &FilenSDK,
+        uuid: String,
+        region: String,
+        bucket: String,
+        key: String,
+        size: u64,
+        download_funcs: F,
+    ) -> Self {
+        Self {
+            uuid,
+            region,
+            bucket,
+            key,
+            size,
+            download_funcs,
+            download_semaphore: filen_sdk.download_semaphore.clone(),
+            decrypt_semaphore: filen_sdk.decrypt_semaphore.clone(),
+            position: 0,
+            cache: ChunkCache::new(READER_CACHE_CHUNKS),
+            in_flight: HashMap::new(),
+            last_chunk: None,
+        }
+    }
+
+    /// Size of the decrypted file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Wraps the reader for use with `std::io::Read` and `std::io::Seek`. Must be called from
+    /// within a tokio runtime, and the returned reader must only be used outside of it, e.g. in
+    /// `tokio::task::spawn_blocking`.
+    pub fn into_blocking(self) -> SyncIoBridge<Self> {
+        SyncIoBridge::new(self)
+    }
+
+    fn chunk_count(&self) -> u64 {
+        self.size.div_ceil(CHUNK_SIZE as u64)
+    }
+
+    /// Starts fetching chunk `i` unless it is cached, already being fetched or past the end
+    fn fetch(&mut self, i: u64) {
+        if i >= self.chunk_count() || self.cache.contains(i) || self.in_flight.contains_key(&i) {
+            return;
+        }
+
+        let link = FsURL::Egest(self.region.clone(), self.bucket.clone(), self.uuid.clone(), i);
+        let handle = tokio::spawn(FilenSDK::fetch_plaintext_chunk(
+            link,
+            i,
+            self.key.clone(),
+            self.download_funcs.clone(),
+            self.download_semaphore.clone(),
+            self.decrypt_semaphore.clone(),
+        ));
+        self.in_flight.insert(i, handle);
+    }
+}
+
+impl<F> AsyncRead for FilenFileReader<F>
+where
+    F: FilenNetInteractionFunctions<Bytes> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.position >= this.size || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let i = this.position / CHUNK_SIZE as u64;
+        let chunk = loop {
+            if let Some(chunk) = this.cache.get(i) {
+                break chunk;
+            }
+
+            this.fetch(i);
+            let handle = this.in_flight.get_mut(&i).unwrap();
+            let result = ready!(Pin::new(handle).poll(cx));
+            this.in_flight.remove(&i);
+
+            let data = result.map_err(io::Error::other)?.map_err(io::Error::other)?;
+            this.cache.insert(i, data);
+        };
+
+        if this.last_chunk.is_some_and(|last| i == last + 1) {
+            for next in i + 1..=i + READER_PREFETCH_CHUNKS {
+                this.fetch(next);
+            }
+        }
+        this.last_chunk = Some(i);
+
+        let offset = (this.position - i * CHUNK_SIZE as u64) as usize;
+        if offset >= chunk.len() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Chunk {} is shorter than expected", i),
+            )));
+        }
+
+        let len = std::cmp::min(buf.remaining(), chunk.len() - offset);
+        buf.put_slice(&chunk[offset..offset + len]);
+        this.position += len as u64;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<F> AsyncSeek for FilenFileReader<F>
+where
+    F: FilenNetInteractionFunctions<Bytes> + Unpin,
+{
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })?;
+        this.position = target;
+
+        // Prefetches for the previous location are no longer useful
+        let chunk = target / CHUNK_SIZE as u64;
+        this.in_flight.retain(|i, handle| {
+            let keep = (chunk..=chunk + READER_PREFETCH_CHUNKS).contains(i);
+            if !keep {
+                handle.abort();
+            }
+            keep
+        });
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl<F> Drop for FilenFileReader<F> {
+    fn drop(&mut self) {
+        for handle in self.in_flight.values() {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek};
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    use super::*;
+    use crate::{crypto::generate_rand_key, mod_private::net_interaction::mock::MockInteractionFunctions};
+
+    fn reader(sdk: &FilenSDK, data: &[u8]) -> FilenFileReader<MockInteractionFunctions> {
+        let key = generate_rand_key().unwrap();
+        FilenFileReader::with_download_funcs(
+            sdk,
+            "uuid".to_string(),
+            "region".to_string(),
+            "bucket".to_string(),
+            String::from_utf8(key.to_vec()).unwrap(),
+            data.len() as u64,
+            MockInteractionFunctions::from_plaintext(data, &key),
+        )
+    }
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 253) as u8).collect()
+    }
+
+    #[test]
+    fn test_chunk_cache_eviction() {
+        let mut cache = ChunkCache::new(2);
+        cache.insert(0, Bytes::from_static(b"0"));
+        cache.insert(1, Bytes::from_static(b"1"));
+
+        // Using chunk 0 makes chunk 1 the least recently used
+        assert!(cache.get(0).is_some());
+        cache.insert(2, Bytes::from_static(b"2"));
+
+        assert!(cache.contains(0));
+        assert!(!cache.contains(1));
+        assert!(cache.contains(2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_read_and_seek() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 3 + 100);
+        let mut reader = reader(&sdk, &data);
+
+        let mut whole = Vec::new();
+        reader.read_to_end(&mut whole).await.unwrap();
+        assert_eq!(whole, data);
+
+        // Across a chunk boundary
+        let mut buf = vec![0; 20];
+        reader.seek(SeekFrom::Start(CHUNK_SIZE as u64 - 10)).await.unwrap();
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, &data[CHUNK_SIZE - 10..CHUNK_SIZE + 10]);
+
+        reader.seek(SeekFrom::Current(-30)).await.unwrap();
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, &data[CHUNK_SIZE - 20..CHUNK_SIZE]);
+
+        reader.seek(SeekFrom::End(-5)).await.unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).await.unwrap();
+        assert_eq!(tail, &data[data.len() - 5..]);
+
+        // Past the end reads nothing, before the start is an error
+        reader.seek(SeekFrom::End(10)).await.unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-(data.len() as i64) - 20)).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocking_reader() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE + 10);
+        let mut reader = reader(&sdk, &data).into_blocking();
+
+        let expected = data.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut buf = vec![0; 20];
+            reader.seek(SeekFrom::Start(CHUNK_SIZE as u64 - 10)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, &expected[CHUNK_SIZE - 10..]);
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -1,11 +1,16 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{atomic::AtomicUsize, Arc, Mutex},
+    time::Duration,
+};
 
 use tokio::sync::Semaphore;
 
 use crate::{
+    chunk_cache::EncryptedChunkCache,
     credentials::SDKCreds,
     error::FilenSDKError,
     httpclient::{http_none, make_request, Endpoints},
+    path::PathCache,
     requests::auth::LoginRequest,
     responses::auth::{AuthVersion, LoginResponse, UserInfoResponse}
 };
@@ -19,14 +24,46 @@ pub struct FilenSDK {
     pub(crate) download_semaphore: Arc<Semaphore>,
     /// See Download Semaphore
     pub(crate) upload_semaphore: Arc<Semaphore>,
+    /// Limits the number of chunks decrypted at once on blocking threads, which bounds both CPU
+    /// usage and the memory held by chunks waiting to be decrypted
+    pub(crate) decrypt_semaphore: Arc<Semaphore>,
+    /// Bytes reserved by queued read-ahead chunks across all streaming downloads, streams shrink
+    /// their read-ahead window while this exceeds MAX_READ_AHEAD_MEMORY
+    pub(crate) read_ahead_bytes: Arc<AtomicUsize>,
     pub(crate) client: Arc<reqwest::Client>,
-    pub(crate) tokio_runtime: Arc<Mutex<Option<tokio::runtime::Runtime>>>
+    pub(crate) tokio_runtime: Arc<Mutex<Option<tokio::runtime::Runtime>>>,
+    /// Resolved remote path segments, see `resolve_path`
+    pub(crate) path_cache: Arc<Mutex<PathCache>>,
+    /// Encrypted chunks kept on disk across downloads, see `enable_chunk_cache`
+    pub(crate) chunk_cache: Arc<EncryptedChunkCache>,
 }
 
 pub const MAX_DECRYPT_THREADS: usize = 10;
+pub const MAX_ENCRYPT_THREADS: usize = 8;
 pub const MAX_DOWNLOAD_THREADS: usize = 50;
 pub const MAX_UPLOAD_THREADS: usize = 50;
 pub const MAX_READ_AHEAD_THREADS: u64 = 50;
+pub const MIN_READ_AHEAD_CHUNKS: u64 = 2;
+pub const MAX_READ_AHEAD_MEMORY: usize = 256 * 1024 * 1024;
+/// Chunks an ordered download may hold ahead of the consumer, see `ordered_download_stream`
+pub const MAX_REORDER_CHUNKS: usize = 16;
+/// How often a resumable upload saves its progress to the session file
+pub const UPLOAD_SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a single file download syncs its partial file and records the synced chunks in
+/// its manifest
+pub const DOWNLOAD_MANIFEST_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_DIR_LIST_THREADS: usize = 10;
+pub const MAX_FOLDER_DOWNLOAD_FILES: usize = 4;
+pub const MAX_FOLDER_UPLOAD_FILES: usize = 4;
+/// Consecutive failures after which a storage mirror is skipped for MIRROR_COOLDOWN
+pub const MIRROR_FAILURE_THRESHOLD: u32 = 3;
+pub const MIRROR_COOLDOWN: Duration = Duration::from_secs(30);
+/// Mirrors tried for a single chunk request before its error is returned
+pub const MIRROR_FAILOVER_ATTEMPTS: usize = 3;
+/// Decrypted chunks kept in memory by a `FilenFileReader`
+pub const READER_CACHE_CHUNKS: usize = 16;
+/// Chunks fetched ahead of a `FilenFileReader` while it is read linearly
+pub const READER_PREFETCH_CHUNKS: u64 = 4;
 
 #[uniffi::export]
 impl FilenSDK {
@@ -52,8 +89,12 @@ impl FilenSDK {
             credentials: Arc::new(Mutex::new(None)),
             download_semaphore: Arc::new(Semaphore::new(MAX_DOWNLOAD_THREADS)),
             upload_semaphore: Arc::new(Semaphore::new(MAX_UPLOAD_THREADS)),
+            decrypt_semaphore: Arc::new(Semaphore::new(MAX_DECRYPT_THREADS)),
+            read_ahead_bytes: Arc::new(AtomicUsize::new(0)),
             client: Arc::new(client),
-            tokio_runtime: Arc::new(Mutex::new(run_time))
+            tokio_runtime: Arc::new(Mutex::new(run_time)),
+            path_cache: Arc::new(Mutex::new(PathCache::default())),
+            chunk_cache: Arc::new(EncryptedChunkCache::default()),
         }
     }
 
@@ -0,0 +1,453 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{StreamExt, TryStreamExt};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{
+    dir::DecryptedStreamedDirContentResponse,
+    error::FilenSDKError,
+    file::FilenFileDetailed,
+    filensdk::MAX_FOLDER_DOWNLOAD_FILES,
+    httpclient::{ChunkSource, OrderlessDownloadOptions},
+    mod_private::net_interaction::LowDiskInteractionFunctions,
+    tree::{FilenTreeEntry, FilenWalkOptions},
+    FilenSDK,
+};
+
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenFolderDownloadOptions {
+    /// Glob patterns matched against paths relative to the downloaded folder, e.g.
+    /// `photos/**/*.jpg`. `*` does not cross folder boundaries, `**` does. Empty includes every
+    /// file.
+    pub include: Vec<String>,
+    /// Files matching any of these patterns, or inside a folder matching one, are skipped.
+    pub exclude: Vec<String>,
+    /// Number of files downloaded at once, defaults to MAX_FOLDER_DOWNLOAD_FILES. Chunks are
+    /// additionally limited by the SDK's download semaphore.
+    pub concurrency: Option<u32>,
+    /// Skip files whose local copy already has the same size and modification time
+    pub skip_unchanged: bool,
+}
+
+impl Default for FilenFolderDownloadOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            concurrency: None,
+            skip_unchanged: true,
+        }
+    }
+}
+
+/// Aggregate progress of a folder download. Skipped files count as completed. The download is
+/// done once `files_completed + files_failed` reaches `files_total`, bytes of failed files never
+/// count as completed.
+#[derive(uniffi::Record, Debug, Clone, Default, PartialEq)]
+pub struct FilenFolderDownloadProgress {
+    pub files_total: u64,
+    pub files_completed: u64,
+    pub files_skipped: u64,
+    pub files_failed: u64,
+    pub bytes_total: u64,
+    pub bytes_completed: u64,
+}
+
+/// A file or folder `download_folder` could not download, by its path relative to the
+/// downloaded folder
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct FilenFolderDownloadFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenFolderDownloadResult {
+    pub progress: FilenFolderDownloadProgress,
+    pub failures: Vec<FilenFolderDownloadFailure>,
+}
+
+/// Receives progress updates from `download_folder`, once before the first file and after
+/// every completed or failed file.
+#[uniffi::export(with_foreign)]
+pub trait FilenFolderDownloadListener: Send + Sync {
+    fn on_progress(&self, progress: FilenFolderDownloadProgress);
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Downloads the folder `uuid` into `local_dir`, recreating its tree of folders.
+    ///
+    /// Files are downloaded concurrently through the resumable single file download, verified
+    /// against their metadata, and their modification time is restored from it. A file that
+    /// fails is recorded in the result and the rest of the batch carries on. Only listing the
+    /// remote folder, an unusable `local_dir` or invalid patterns fail the whole call.
+    pub async fn download_folder(
+        &self,
+        uuid: String,
+        local_dir: String,
+        options: FilenFolderDownloadOptions,
+        listener: Option<Arc<dyn FilenFolderDownloadListener>>,
+    ) -> Result<FilenFolderDownloadResult, FilenSDKError> {
+        let filter = PathFilter::new(&options.include, &options.exclude)?;
+        let local_dir = PathBuf::from(local_dir);
+        tokio::fs::create_dir_all(&local_dir).await?;
+
+        let entries: Vec<FilenTreeEntry> = self
+            .walk_tree(uuid, FilenWalkOptions::default())
+            .try_collect()
+            .await?;
+
+        let mut files = Vec::new();
+        let mut failures = Vec::new();
+        for entry in entries {
+            if filter.is_excluded(&entry.relative_path) {
+                continue;
+            }
+            let is_file = matches!(entry.item, DecryptedStreamedDirContentResponse::Uploads(_));
+            if is_file && !filter.is_included(&entry.relative_path) {
+                continue;
+            }
+
+            let path = match local_path(&local_dir, &entry.relative_path) {
+                Ok(path) => path,
+                Err(err) => {
+                    failures.push(FilenFolderDownloadFailure {
+                        path: entry.relative_path,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            match entry.item {
+                // With include patterns, only folders leading to included files are created
+                DecryptedStreamedDirContentResponse::Folders(_) => {
+                    if filter.include.is_some() {
+                        continue;
+                    }
+                    if let Err(err) = tokio::fs::create_dir_all(&path).await {
+                        failures.push(FilenFolderDownloadFailure {
+                            path: entry.relative_path,
+                            error: err.to_string(),
+                        });
+                    }
+                }
+                DecryptedStreamedDirContentResponse::Uploads(file) => {
+                    files.push((entry.relative_path, path, file));
+                }
+            }
+        }
+
+        let mut progress = FilenFolderDownloadProgress {
+            files_total: files.len() as u64,
+            bytes_total: files.iter().map(|(_, _, file)| file.size).sum(),
+            ..Default::default()
+        };
+        report_progress(&listener, &progress);
+
+        let concurrency = options
+            .concurrency
+            .map_or(MAX_FOLDER_DOWNLOAD_FILES, |c| c.max(1) as usize);
+        let mut downloads = futures::stream::iter(files)
+            .map(|(relative_path, path, file)| async move {
+                let skipped = options.skip_unchanged
+                    && tokio::fs::metadata(&path)
+                        .await
+                        .is_ok_and(|metadata| is_unchanged(&metadata, &file));
+
+                let result = match skipped {
+                    true => Ok(()),
+                    false => self.download_folder_file(&path, &file).await,
+                };
+                (relative_path, file.size, skipped, result)
+            })
+            .buffer_unordered(concurrency);
+
+        while let Some((relative_path, size, skipped, result)) = downloads.next().await {
+            match result {
+                Ok(()) => {
+                    progress.files_completed += 1;
+                    progress.bytes_completed += size;
+                    if skipped {
+                        progress.files_skipped += 1;
+                    }
+                }
+                Err(err) => {
+                    progress.files_failed += 1;
+                    failures.push(FilenFolderDownloadFailure {
+                        path: relative_path,
+                        error: err.to_string(),
+                    });
+                }
+            }
+            report_progress(&listener, &progress);
+        }
+
+        Ok(FilenFolderDownloadResult { progress, failures })
+    }
+}
+
+impl FilenSDK {
+    async fn download_folder_file(
+        &self,
+        path: &Path,
+        file: &FilenFileDetailed,
+    ) -> Result<(), FilenSDKError> {
+        let invalid_path = || FilenSDKError::InvalidPath {
+            path: path.display().to_string(),
+        };
+        let output_dir = path.parent().ok_or_else(invalid_path)?;
+        let output_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(invalid_path)?;
+
+        self.orderless_file_download(
+            ChunkSource::try_from(file)?,
+            output_dir,
+            OrderlessDownloadOptions {
+                output_name: Some(output_name.to_string()),
+                verify: true,
+                ..Default::default()
+            },
+            LowDiskInteractionFunctions {
+                client: self.client.clone(),
+                api_key: "".to_string(),
+                should_use_counter_nonce: false,
+                chunk_cache: Some(self.chunk_cache.clone()),
+            },
+        )
+        .await?;
+
+        if let Some(modified) = remote_modified(file) {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || {
+                std::fs::File::options()
+                    .write(true)
+                    .open(path)?
+                    .set_modified(modified)
+            })
+            .await??;
+        }
+
+        Ok(())
+    }
+}
+
+fn report_progress(
+    listener: &Option<Arc<dyn FilenFolderDownloadListener>>,
+    progress: &FilenFolderDownloadProgress,
+) {
+    if let Some(listener) = listener {
+        listener.on_progress(progress.clone());
+    }
+}
+
+fn remote_modified(file: &FilenFileDetailed) -> Option<SystemTime> {
+    let millis = u64::try_from(file.last_modified?).ok()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// Whether a local file matches the remote size and modification time. Times are compared in
+/// whole seconds, since not every filesystem stores milliseconds.
+fn is_unchanged(metadata: &std::fs::Metadata, file: &FilenFileDetailed) -> bool {
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+
+    let local_modified = metadata.modified().ok().and_then(seconds);
+    let remote_modified = remote_modified(file).and_then(seconds);
+
+    metadata.is_file()
+        && metadata.len() == file.size
+        && remote_modified.is_some()
+        && local_modified == remote_modified
+}
+
+/// Maps a path relative to the downloaded folder below `local_dir`, refusing names that would
+/// escape it.
+fn local_path(local_dir: &Path, relative_path: &str) -> Result<PathBuf, FilenSDKError> {
+    let mut path = local_dir.to_path_buf();
+
+    for segment in relative_path.split('/') {
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !segment.contains('\\') => path.push(segment),
+            _ => {
+                return Err(FilenSDKError::InvalidPath {
+                    path: relative_path.to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+/// Include and exclude glob patterns over `/` separated relative paths, shared with
+/// `upload_folder`
+pub(crate) struct PathFilter {
+    pub(crate) include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Result<Self, FilenSDKError> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_glob_set(include)?)
+            },
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    pub(crate) fn is_included(&self, relative_path: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative_path))
+    }
+
+    /// Whether the path itself or any folder above it is excluded
+    pub(crate) fn is_excluded(&self, relative_path: &str) -> bool {
+        relative_path
+            .match_indices('/')
+            .map(|(index, _)| &relative_path[..index])
+            .chain(std::iter::once(relative_path))
+            .any(|path| self.exclude.is_match(path))
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, FilenSDKError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| FilenSDKError::InvalidPattern {
+                pattern: pattern.clone(),
+                err_str: err.to_string(),
+            })?;
+        builder.add(glob);
+    }
+
+    builder.build().map_err(|err| FilenSDKError::InvalidPattern {
+        pattern: patterns.join(", "),
+        err_str: err.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(
+            &patterns(&["photos/**/*.jpg", "*.txt"]),
+            &patterns(&["**/node_modules", "photos/private"]),
+        )
+        .unwrap();
+
+        assert!(filter.is_included("photos/2024/a.jpg"));
+        assert!(filter.is_included("notes.txt"));
+        assert!(!filter.is_included("docs/notes.txt"));
+        assert!(!filter.is_included("photos/a.png"));
+
+        // Excluded folders exclude everything below them
+        assert!(filter.is_excluded("app/node_modules/lib/index.js"));
+        assert!(filter.is_excluded("photos/private/a.jpg"));
+        assert!(!filter.is_excluded("photos/public/a.jpg"));
+
+        assert!(PathFilter::new(&patterns(&["a/{b"]), &[]).is_err());
+    }
+
+    #[test]
+    fn test_local_path() {
+        let root = Path::new("/tmp/out");
+        assert_eq!(local_path(root, "a/b.txt").unwrap(), root.join("a").join("b.txt"));
+        assert!(local_path(root, "a/../../etc/passwd").is_err());
+        assert!(local_path(root, "a//b").is_err());
+        assert!(local_path(root, "a\\..\\b").is_err());
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"hello").unwrap();
+
+        let mut file = FilenFileDetailed {
+            uuid: String::new(),
+            region: String::new(),
+            bucket: String::new(),
+            name: String::new(),
+            size: 5,
+            mime: String::new(),
+            key: Vec::new(),
+            last_modified: Some(1_700_000_000_123),
+            hash: None,
+            parent: String::new(),
+            versioned: None,
+            trash: false,
+            version: crate::responses::auth::AuthVersion::V2,
+        };
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(remote_modified(&file).unwrap())
+            .unwrap();
+
+        assert!(is_unchanged(&std::fs::metadata(&path).unwrap(), &file));
+
+        file.size = 6;
+        assert!(!is_unchanged(&std::fs::metadata(&path).unwrap(), &file));
+
+        file.size = 5;
+        file.last_modified = None;
+        assert!(!is_unchanged(&std::fs::metadata(&path).unwrap(), &file));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A local file uploaded by this SDK and listed again counts as unchanged
+    #[test]
+    fn test_uploaded_file_is_unchanged() {
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"hello").unwrap();
+        let local = std::fs::metadata(&path).unwrap();
+
+        let mut uploaded = crate::httpclient::fs_upload::tests::uploaded_file(local.modified().unwrap());
+        uploaded.size = local.len();
+        let metadata = uploaded.metadata();
+        let file = FilenFileDetailed {
+            uuid: String::new(),
+            region: String::new(),
+            bucket: String::new(),
+            name: metadata.name,
+            size: local.len(),
+            mime: String::new(),
+            key: Vec::new(),
+            last_modified: metadata.last_modified,
+            hash: None,
+            parent: String::new(),
+            versioned: None,
+            trash: false,
+            version: crate::responses::auth::AuthVersion::V2,
+        };
+
+        let drift = local.modified().unwrap().duration_since(remote_modified(&file).unwrap()).unwrap();
+        assert!(drift < Duration::from_millis(1));
+        assert!(is_unchanged(&local, &file));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{
+    error::FilenSDKError,
+    filensdk::MAX_FOLDER_UPLOAD_FILES,
+    folder_download::PathFilter,
+    upload::UploadConflictPolicy,
+    FilenSDK,
+};
+
+/// How `upload_folder` treats symbolic links in the local tree
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilenSymlinkPolicy {
+    /// Leave symbolic links out of the upload
+    #[default]
+    Skip,
+    /// Upload what the link points to. Links that point back into a folder above them are
+    /// reported as failures instead of being followed forever.
+    Follow,
+}
+
+#[derive(uniffi::Record, Debug, Clone, Default)]
+pub struct FilenFolderUploadOptions {
+    /// Glob patterns matched against paths relative to the uploaded folder, see
+    /// `FilenFolderDownloadOptions::include`. Empty includes every file.
+    pub include: Vec<String>,
+    /// Files matching any of these patterns, or inside a folder matching one, are skipped.
+    pub exclude: Vec<String>,
+    /// Number of files uploaded at once, defaults to MAX_FOLDER_UPLOAD_FILES. Chunks are
+    /// additionally limited by the SDK's upload semaphore.
+    pub concurrency: Option<u32>,
+    pub symlinks: FilenSymlinkPolicy,
+    /// Applied to every file that already exists in its target folder
+    pub conflict_policy: UploadConflictPolicy,
+}
+
+/// Aggregate progress of a folder upload. The upload is done once `files_completed +
+/// files_failed` reaches `files_total`, bytes of failed files never count as completed.
+#[derive(uniffi::Record, Debug, Clone, Default, PartialEq)]
+pub struct FilenFolderUploadProgress {
+    pub files_total: u64,
+    pub files_completed: u64,
+    pub files_failed: u64,
+    pub bytes_total: u64,
+    pub bytes_completed: u64,
+}
+
+/// A file or folder `upload_folder` could not upload, by its path relative to the local folder
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct FilenFolderUploadFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenFolderUploadResult {
+    pub progress: FilenFolderUploadProgress,
+    pub failures: Vec<FilenFolderUploadFailure>,
+}
+
+/// Receives progress updates from `upload_folder`, once before the first file and after every
+/// uploaded or failed file.
+#[uniffi::export(with_foreign)]
+pub trait FilenFolderUploadListener: Send + Sync {
+    fn on_progress(&self, progress: FilenFolderUploadProgress);
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Uploads the contents of `local_dir` into the folder `parent_uuid`, recreating its tree of
+    /// folders. Folders that already exist remotely are reused.
+    ///
+    /// Files are uploaded concurrently with `upload_file_with_policy`. A file that fails is
+    /// recorded in the result and the rest of the batch carries on, a folder that cannot be
+    /// created fails everything below it. Only an unreadable `local_dir` or invalid patterns
+    /// fail the whole call.
+    pub async fn upload_folder(
+        &self,
+        local_dir: String,
+        parent_uuid: String,
+        options: FilenFolderUploadOptions,
+        listener: Option<Arc<dyn FilenFolderUploadListener>>,
+    ) -> Result<FilenFolderUploadResult, FilenSDKError> {
+        let filter = PathFilter::new(&options.include, &options.exclude)?;
+        let root = PathBuf::from(local_dir);
+        let symlinks = options.symlinks;
+        let walk = tokio::task::spawn_blocking(move || walk_local_dir(&root, &filter, symlinks)).await??;
+
+        let concurrency = options
+            .concurrency
+            .map_or(MAX_FOLDER_UPLOAD_FILES, |c| c.max(1) as usize);
+        let mut failures = walk.failures;
+
+        // Folders are created level by level, so every parent exists before its children
+        let mut levels: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for folder in walk.folders {
+            levels.entry(folder.matches('/').count()).or_default().push(folder);
+        }
+        let mut folders: HashMap<String, Result<String, String>> = HashMap::new();
+        folders.insert(String::new(), Ok(parent_uuid));
+        for level in levels.into_values() {
+            // Filen compares names case-insensitively, so `Photos` and `photos` are one remote
+            // folder. Create it once instead of racing two `create_folder` calls.
+            let created: Vec<(Vec<String>, Result<String, String>)> =
+                futures::stream::iter(group_case_variants(level))
+                    .map(|group| {
+                        let (parent, name) = split_parent(&group[0]);
+                        let parent = folders[parent].clone();
+                        let name = name.to_string();
+                        async move {
+                            let result = match parent {
+                                Ok(parent) => self
+                                    .ensure_folder(parent, name)
+                                    .await
+                                    .map_err(|e| e.to_string()),
+                                Err(e) => Err(e),
+                            };
+                            (group, result)
+                        }
+                    })
+                    .buffer_unordered(concurrency)
+                    .collect()
+                    .await;
+
+            for (group, result) in created {
+                for relative_path in group {
+                    let parent_failed = folders[split_parent(&relative_path).0].is_err();
+                    if let (Err(error), false) = (&result, parent_failed) {
+                        failures.push(FilenFolderUploadFailure {
+                            path: relative_path.clone(),
+                            error: error.clone(),
+                        });
+                    }
+                    folders.insert(relative_path, result.clone());
+                }
+            }
+        }
+
+        let mut progress = FilenFolderUploadProgress {
+            files_total: walk.files.len() as u64,
+            bytes_total: walk.files.iter().map(|file| file.size).sum(),
+            ..Default::default()
+        };
+        report_progress(&listener, &progress);
+
+        let mut uploads = futures::stream::iter(walk.files)
+            .map(|file| {
+                let (parent, name) = split_parent(&file.relative_path);
+                let parent = folders[parent].clone();
+                let name = name.to_string();
+                async move {
+                    let result = match parent {
+                        Ok(parent) => self
+                            .upload_file_with_policy(
+                                file.path.to_string_lossy().to_string(),
+                                parent,
+                                name,
+                                options.conflict_policy,
+                            )
+                            .await
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(e),
+                    };
+                    (file, result)
+                }
+            })
+            .buffer_unordered(concurrency);
+
+        while let Some((file, result)) = uploads.next().await {
+            match result {
+                Ok(_) => {
+                    progress.files_completed += 1;
+                    progress.bytes_completed += file.size;
+                }
+                Err(error) => {
+                    progress.files_failed += 1;
+                    failures.push(FilenFolderUploadFailure {
+                        path: file.relative_path,
+                        error,
+                    });
+                }
+            }
+            report_progress(&listener, &progress);
+        }
+
+        Ok(FilenFolderUploadResult { progress, failures })
+    }
+}
+
+impl FilenSDK {
+    /// Returns the uuid of the folder `name` in `parent`, creating it if it does not exist
+    async fn ensure_folder(&self, parent: String, name: String) -> Result<String, FilenSDKError> {
+        match self.folder_exists(parent.clone(), name.clone()).await? {
+            Some(uuid) => Ok(uuid),
+            None => self.create_folder(parent, name).await,
+        }
+    }
+}
+
+fn report_progress(
+    listener: &Option<Arc<dyn FilenFolderUploadListener>>,
+    progress: &FilenFolderUploadProgress,
+) {
+    if let Some(listener) = listener {
+        listener.on_progress(progress.clone());
+    }
+}
+
+/// Splits `a/b/c` into `a/b` and `c`, top level paths have the parent `""`
+fn split_parent(relative_path: &str) -> (&str, &str) {
+    relative_path.rsplit_once('/').unwrap_or(("", relative_path))
+}
+
+/// Groups folder paths that only differ in case, keeping the order in which each group first
+/// appears. Parents that differ in case are the same remote folder too, so whole paths are compared.
+fn group_case_variants(folders: Vec<String>) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for folder in folders {
+        match index.get(&folder.to_lowercase()) {
+            Some(&i) => groups[i].push(folder),
+            None => {
+                index.insert(folder.to_lowercase(), groups.len());
+                groups.push(vec![folder]);
+            }
+        }
+    }
+    groups
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct LocalFile {
+    path: PathBuf,
+    relative_path: String,
+    size: u64,
+}
+
+/// What `upload_folder` found below the local folder
+#[derive(Debug, Default)]
+struct LocalWalk {
+    /// Relative paths of the folders to create, parents before children
+    folders: Vec<String>,
+    files: Vec<LocalFile>,
+    failures: Vec<FilenFolderUploadFailure>,
+}
+
+/// Lists the folders and files below `root` that pass `filter`. With include patterns, only
+/// folders leading to included files are listed. Entries that cannot be read are recorded as
+/// failures, only an unreadable `root` is an error.
+fn walk_local_dir(
+    root: &Path,
+    filter: &PathFilter,
+    symlinks: FilenSymlinkPolicy,
+) -> Result<LocalWalk, FilenSDKError> {
+    let mut walk = LocalWalk::default();
+    let ancestors = vec![root.canonicalize()?];
+    walk_local_children(root, "", &ancestors, filter, symlinks, &mut walk, true)?;
+
+    if filter.include.is_some() {
+        let mut needed: Vec<String> = walk
+            .files
+            .iter()
+            .flat_map(|file| {
+                let path = &file.relative_path;
+                path.match_indices('/').map(|(index, _)| path[..index].to_string())
+            })
+            .collect();
+        needed.sort();
+        needed.dedup();
+        walk.folders = needed;
+    }
+
+    Ok(walk)
+}
+
+fn walk_local_children(
+    dir: &Path,
+    relative_dir: &str,
+    ancestors: &[PathBuf],
+    filter: &PathFilter,
+    symlinks: FilenSymlinkPolicy,
+    walk: &mut LocalWalk,
+    is_root: bool,
+) -> Result<(), FilenSDKError> {
+    let mut children = match std::fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>()) {
+        Ok(children) => children,
+        Err(e) if !is_root => {
+            walk.failures.push(FilenFolderUploadFailure {
+                path: relative_dir.to_string(),
+                error: e.to_string(),
+            });
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    children.sort_by_key(|entry| entry.file_name());
+
+    for entry in children {
+        let path = entry.path();
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            walk.failures.push(FilenFolderUploadFailure {
+                path: path.to_string_lossy().to_string(),
+                error: FilenSDKError::InvalidPath { path: path.to_string_lossy().to_string() }.to_string(),
+            });
+            continue;
+        };
+        let relative_path = if relative_dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative_dir, name)
+        };
+        if filter.is_excluded(&relative_path) {
+            continue;
+        }
+
+        let fail = |walk: &mut LocalWalk, error: String| {
+            walk.failures.push(FilenFolderUploadFailure {
+                path: relative_path.clone(),
+                error,
+            })
+        };
+
+        let mut metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                fail(walk, e.to_string());
+                continue;
+            }
+        };
+        if metadata.is_symlink() {
+            if symlinks == FilenSymlinkPolicy::Skip {
+                continue;
+            }
+            metadata = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    fail(walk, format!("Broken symbolic link: {}", e));
+                    continue;
+                }
+            };
+        }
+
+        if metadata.is_dir() {
+            let canonical = match path.canonicalize() {
+                Ok(canonical) => canonical,
+                Err(e) => {
+                    fail(walk, e.to_string());
+                    continue;
+                }
+            };
+            if ancestors.contains(&canonical) {
+                fail(walk, "Symbolic link points to a folder above it".to_string());
+                continue;
+            }
+
+            walk.folders.push(relative_path.clone());
+            let ancestors = [ancestors, &[canonical]].concat();
+            walk_local_children(&path, &relative_path, &ancestors, filter, symlinks, walk, false)?;
+        } else if metadata.is_file() && filter.is_included(&relative_path) {
+            walk.files.push(LocalFile {
+                path,
+                relative_path,
+                size: metadata.len(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn relative_files(walk: &LocalWalk) -> Vec<&str> {
+        walk.files.iter().map(|file| file.relative_path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_group_case_variants() {
+        let groups = group_case_variants(patterns(&["Photos", "docs", "photos"]));
+        assert_eq!(groups, vec![patterns(&["Photos", "photos"]), patterns(&["docs"])]);
+
+        // Children of case variants end up in the same remote folder
+        let groups = group_case_variants(patterns(&["Photos/2024", "photos/2024", "photos/2025"]));
+        assert_eq!(groups, vec![patterns(&["Photos/2024", "photos/2024"]), patterns(&["photos/2025"])]);
+    }
+
+    #[test]
+    fn test_walk_local_dir() {
+        let root = std::env::temp_dir().join(format!("upload-{}", uuid::Uuid::new_v4()));
+        for dir in ["a/b", "a/node_modules", "empty"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["top.txt", "a/one.jpg", "a/b/two.txt", "a/node_modules/lib.js"] {
+            std::fs::write(root.join(file), file).unwrap();
+        }
+
+        let filter = PathFilter::new(&[], &patterns(&["**/node_modules"])).unwrap();
+        let walk = walk_local_dir(&root, &filter, FilenSymlinkPolicy::Skip).unwrap();
+        assert_eq!(walk.folders, ["a", "a/b", "empty"]);
+        assert_eq!(relative_files(&walk), ["a/b/two.txt", "a/one.jpg", "top.txt"]);
+        assert_eq!(walk.files[0].size, "a/b/two.txt".len() as u64);
+        assert!(walk.failures.is_empty());
+
+        // Include patterns only keep the folders leading to included files
+        let filter = PathFilter::new(&patterns(&["**/*.txt"]), &[]).unwrap();
+        let walk = walk_local_dir(&root, &filter, FilenSymlinkPolicy::Skip).unwrap();
+        assert_eq!(walk.folders, ["a", "a/b"]);
+        assert_eq!(relative_files(&walk), ["a/b/two.txt", "top.txt"]);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("a/b"), root.join("link")).unwrap();
+            std::os::unix::fs::symlink(root.join("a"), root.join("a/b/loop")).unwrap();
+            let filter = PathFilter::new(&[], &patterns(&["**/node_modules"])).unwrap();
+
+            let walk = walk_local_dir(&root, &filter, FilenSymlinkPolicy::Skip).unwrap();
+            assert_eq!(relative_files(&walk), ["a/b/two.txt", "a/one.jpg", "top.txt"]);
+
+            let walk = walk_local_dir(&root, &filter, FilenSymlinkPolicy::Follow).unwrap();
+            assert_eq!(walk.folders, ["a", "a/b", "empty", "link", "link/loop"]);
+            assert_eq!(
+                relative_files(&walk),
+                ["a/b/two.txt", "a/one.jpg", "link/loop/one.jpg", "link/two.txt", "top.txt"]
+            );
+            // Following stops where a link leads back into a folder above it
+            let failed: Vec<&str> = walk.failures.iter().map(|f| f.path.as_str()).collect();
+            assert_eq!(failed, ["a/b/loop", "link/loop/b"]);
+        }
+
+        assert!(walk_local_dir(&root.join("missing"), &filter, FilenSymlinkPolicy::Skip).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_split_parent() {
+        assert_eq!(split_parent("a/b/c.txt"), ("a/b", "c.txt"));
+        assert_eq!(split_parent("c.txt"), ("", "c.txt"));
+    }
+}
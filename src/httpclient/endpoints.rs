@@ -1,6 +1,8 @@
+use std::sync::LazyLock;
+
 use url::Url;
 
-use super::httpclient::RequestMethod;
+use super::{httpclient::RequestMethod, mirrors::MirrorPool};
 
 // TODO: Move these to constants
 const BASE_GATEWAY_URL: &str = "https://gateway.filen.io";
@@ -26,6 +28,9 @@ const INGEST_URLS: [&str; 8] = [
     "https://ingest.filen-6.net",
 ];
 
+pub static EGEST_MIRRORS: LazyLock<MirrorPool> = LazyLock::new(|| MirrorPool::new(&EGEST_URLS));
+pub static INGEST_MIRRORS: LazyLock<MirrorPool> = LazyLock::new(|| MirrorPool::new(&INGEST_URLS));
+
 pub struct FilenEndpoint {
     pub endpoint: &'static str,
     pub method: RequestMethod,
@@ -77,9 +82,16 @@ define_endpoints![
     UploadDone => ("/v3/upload/done", POST),
     // File
     FileInfo => ("/v3/file", POST),
+    FileExists => ("/v3/file/exists", POST),
+    FileTrash => ("/v3/file/trash", POST),
 
     // Dir
     DirContent => ("/v3/dir/content", POST),
+    DirInfo => ("/v3/dir", POST),
+    DirCreate => ("/v3/dir/create", POST),
+    DirTree => ("/v3/dir/tree", POST),
+    DirSize => ("/v3/dir/size", POST),
+    DirExists => ("/v3/dir/exists", POST),
 ];
 
 #[derive(Debug, Clone)]
@@ -88,21 +100,28 @@ pub enum FsURL {
     Igest(String, String, u64, String, String), 
 }
 
-pub fn string_url(url: &FsURL) -> Url {
+/// Mirrors able to serve `url`, and the chunk index used to spread requests over them
+pub fn mirror_pool(url: &FsURL) -> (&'static MirrorPool, u64) {
+    match url {
+        FsURL::Egest(_, _, _, index) => (&EGEST_MIRRORS, *index),
+        FsURL::Igest(_, _, index, _, _) => (&INGEST_MIRRORS, *index),
+    }
+}
+
+/// Full URL of `url` on the mirror with base URL `mirror`, see `mirror_pool`
+pub fn string_url(url: &FsURL, mirror: &str) -> Url {
     match url {
         FsURL::Egest(region, bucket, uuid, index) => {
-            let egest_url = EGEST_URLS[*index as usize % EGEST_URLS.len()];
             Url::parse(&format!(
                 "{}/{}/{}/{}/{}",
-                egest_url, region, bucket, uuid, index
+                mirror, region, bucket, uuid, index
             ))
             .unwrap()
         },
         FsURL::Igest(uuid, upload_key, index, parent, hash) => {
-            let ingest_url = INGEST_URLS[*index as usize % INGEST_URLS.len()];
             Url::parse(&format!(
                 "{}/v3/upload?uuid={}&index={}&uploadKey={}&parent={}&hash={}",
-                ingest_url, uuid.to_lowercase(), index, upload_key, parent.to_lowercase(), hash
+                mirror, uuid.to_lowercase(), index, upload_key, parent.to_lowercase(), hash
             ))
             .unwrap()
         }
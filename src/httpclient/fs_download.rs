@@ -1,48 +1,146 @@
 use std::{
     collections::VecDeque,
     future::Future,
-    sync::{atomic::AtomicI8, Arc},
-    time::Duration,
+    sync::{
+        atomic::{AtomicI8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use bytes::{Bytes, BytesMut};
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use tokio::{
     runtime::{EnterGuard, Handle, Runtime},
-    task::JoinHandle,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
 };
 
 use super::FsURL;
 use crate::{
-    crypto::file_decrypt::{decrypt_v2_bytes, write_output, write_output_async},
+    chunk_cache::EncryptedChunkCache,
+    crypto::{
+        file_decrypt::{decrypt_v2_bytes, write_output, write_output_at},
+        file_encrypt::read_exact_at,
+    },
     error::FilenSDKError,
-    filensdk::{MAX_DOWNLOAD_THREADS, MAX_READ_AHEAD_THREADS},
+    file::FilenFileDetailed,
+    filensdk::{MAX_DOWNLOAD_THREADS, MAX_READ_AHEAD_MEMORY, MAX_REORDER_CHUNKS},
     httpclient::calculate_chunk_range,
-    mod_private::net_interaction::{
-        FilenNetInteractionFunctions, LowDiskInteractionFunctions,
+    mod_private::{
+        download_manifest::DownloadManifestHeader,
+        integrity::OrderedHasher,
+        net_interaction::{FilenNetInteractionFunctions, LowDiskInteractionFunctions},
+        partial_download::PartialDownload,
+        read_ahead::{ReadAheadTask, ReadAheadWindow},
     },
     FilenSDK, CHUNK_SIZE,
 };
 
+/// Remote file whose chunks the download pipelines fetch
+#[derive(Debug, Clone)]
+pub struct ChunkSource {
+    pub uuid: String,
+    pub region: String,
+    pub bucket: String,
+    /// Key the chunks are encrypted with
+    pub key: String,
+    pub size: u64,
+    /// SHA-512 hex digest of the plaintext from the file metadata, if any
+    pub hash: Option<String>,
+}
+
+impl TryFrom<&FilenFileDetailed> for ChunkSource {
+    type Error = FilenSDKError;
+
+    fn try_from(file: &FilenFileDetailed) -> Result<Self, FilenSDKError> {
+        Ok(Self {
+            uuid: file.uuid.clone(),
+            region: file.region.clone(),
+            bucket: file.bucket.clone(),
+            key: String::from_utf8(file.key.clone())?,
+            size: file.size,
+            hash: file.hash.clone(),
+        })
+    }
+}
+
+/// What an `orderless_file_download` writes
+#[derive(Debug, Clone, Default)]
+pub struct OrderlessDownloadOptions {
+    /// Single output file inside the output directory, which makes the download resumable.
+    /// Without one, every chunk is written to its own file named after its index.
+    pub output_name: Option<String>,
+    /// Inclusive start of the byte range
+    pub start_byte: u64,
+    /// Exclusive end of the byte range, `None` for the end of the file
+    pub end_byte: Option<u64>,
+    /// Trim the first and last chunks to exactly the byte range instead of writing whole chunks
+    pub exact_range: bool,
+    /// Check downloads of the whole file against the size and hash of the source before the
+    /// output is published, see `PlaintextHasher::verify_parts`
+    pub verify: bool,
+}
+
+/// Chunk handed from the download tasks to the decrypt workers of `orderless_file_download`
+struct ReceivedChunk<T> {
+    i: u64,
+    data: ChunkData<T>,
+    /// Held until the chunk is hashed, when verifying
+    window_permit: Option<OwnedSemaphorePermit>,
+}
+
+enum ChunkData<T> {
+    Downloaded(FsURL, T),
+    Failed,
+    /// Written by a previous attempt, only read back to be hashed
+    Resumed,
+}
+
 impl FilenSDK {
     /// This method of download does not care about the order of the chunks, and will download them in parallel.
     /// This is useful for downloading large files, when streaming is not necessary.
+    ///
+    /// When downloading into a single output file, chunks are written into a preallocated
+    /// `<output>.filendownload` file which is synced and atomically renamed to the output once
+    /// every chunk has been written. Completed chunks are recorded in a `<output>.filenpart`
+    /// manifest. If the download fails, calling this function again with the same arguments only
+    /// downloads the missing chunks.
+    ///
+    /// Whole chunks covering the byte range of `options` are written unless `exact_range` is
+    /// set, in which case the first and last chunks are trimmed so that the output holds exactly
+    /// the requested bytes. The returned range is the range of the file that was written.
+    ///
+    /// With `verify`, whole file downloads hash the plaintext in chunk order as it is written,
+    /// including chunks resumed from a previous attempt, and return an `IntegrityError` on a
+    /// mismatch. A mismatching partial file is removed instead of being renamed to the output.
     pub async fn orderless_file_download<T>(
         &self,
-        uuid: &str,
-        region: &str,
-        bucket: &str,
-        key: String,
+        source: ChunkSource,
         output_dir: &std::path::Path,
-        output_name: Option<String>,
-        file_size: u64,
-        byte_range_start: u64,
-        byte_range_end: u64,
+        options: OrderlessDownloadOptions,
         download_funcs: impl FilenNetInteractionFunctions<T>,
     ) -> Result<(u64, u64), FilenSDKError>
     where
         T: Send + Sync + 'static,
     {
+        let ChunkSource {
+            uuid,
+            region,
+            bucket,
+            key,
+            size: file_size,
+            hash,
+        } = source;
+        let OrderlessDownloadOptions {
+            output_name,
+            start_byte: byte_range_start,
+            end_byte,
+            exact_range,
+            verify,
+        } = options;
+        let byte_range_end = end_byte.unwrap_or(file_size);
+
         // Create output directory if it does not exist
         std::fs::create_dir_all(output_dir)?;
 
@@ -57,23 +155,95 @@ impl FilenSDK {
 
         // Start channel for finished tasks to notify completion
         let (tx_decrypt, mut rx_decrypt) =
-            tokio::sync::mpsc::channel::<(u64, Option<T>)>(MAX_DOWNLOAD_THREADS);
+            tokio::sync::mpsc::channel::<ReceivedChunk<T>>(MAX_DOWNLOAD_THREADS);
 
         // Calculate start and end chunk range
         let (start_chunk, end_chunk) =
             calculate_chunk_range(byte_range_start, byte_range_end, file_size);
 
+        let (range_start, range_end) = if exact_range {
+            (byte_range_start, std::cmp::min(byte_range_end, file_size))
+        } else {
+            (
+                start_chunk * CHUNK_SIZE as u64,
+                std::cmp::min(end_chunk * CHUNK_SIZE as u64, file_size),
+            )
+        };
+        if range_start > range_end {
+            return Err(FilenSDKError::InvalidByteRange {
+                start_byte: byte_range_start,
+                end_byte: byte_range_end,
+                file_size,
+            });
+        }
+
+        // Only whole files can be checked against their metadata
+        let hasher = (verify && range_start == 0 && range_end == file_size)
+            .then(|| Arc::new(OrderedHasher::new()));
+
+        // Single file downloads go through a partial file that records its progress, so that a
+        // failed download can be resumed by calling this function again
+        let mut partial = match &output_name {
+            Some(output_name) => Some(
+                PartialDownload::open(
+                    &output_dir.join(output_name),
+                    DownloadManifestHeader {
+                        uuid: uuid.clone(),
+                        size: file_size,
+                        key_hash: crate::crypto::metadata::hash_fn(&key)?,
+                        start_chunk,
+                        end_chunk,
+                        start_byte: range_start,
+                        end_byte: range_end,
+                    },
+                    range_end - range_start,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        let completed = partial
+            .as_ref()
+            .map(|partial| partial.completed.clone())
+            .unwrap_or_default();
+        let mut remaining_chunks = (end_chunk - start_chunk) - completed.len() as u64;
+
         let output_file_path = output_dir.to_path_buf();
         let semaphore = self.download_semaphore.clone();
+        let verifying = hasher.is_some();
 
-        let uuid = uuid.to_string();
-        let region = region.to_string();
-        let bucket = bucket.to_string();
-
+        let chunk_uuid = uuid.clone();
         let cloned_download_funcs = download_funcs.clone();
-        tokio::spawn(async move {
-            // Start download threads
+        let dispatcher = tokio::spawn(async move {
+            // A verified chunk is held until every chunk before it has been hashed. Dispatching
+            // chunks in order, at most MAX_REORDER_CHUNKS ahead of the oldest unhashed one, bounds
+            // the chunks held and guarantees the oldest one is always on its way.
+            let hash_window = Arc::new(Semaphore::new(MAX_REORDER_CHUNKS));
+
             for i in start_chunk..end_chunk {
+                let resumed = completed.contains(&i);
+                if resumed && !verifying {
+                    continue;
+                }
+
+                let window_permit = match verifying {
+                    true => Some(hash_window.clone().acquire_owned().await.unwrap()),
+                    false => None,
+                };
+
+                // Chunks completed by a previous attempt are only read back to be hashed
+                if resumed {
+                    let chunk = ReceivedChunk {
+                        i,
+                        data: ChunkData::Resumed,
+                        window_permit,
+                    };
+                    if tx_decrypt.send(chunk).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+
                 let semaphore_reserve = semaphore.clone();
                 let permit = semaphore_reserve.acquire_owned().await;
                 let tx_decrypt = tx_decrypt.clone();
@@ -81,7 +251,7 @@ impl FilenSDK {
                 let link = crate::httpclient::FsURL::Egest(
                     region.to_string(),
                     bucket.to_string(),
-                    uuid.to_string(),
+                    chunk_uuid.to_string(),
                     i,
                 );
 
@@ -89,149 +259,608 @@ impl FilenSDK {
 
                 tokio::spawn(async move {
                     let _moved_permit = permit;
-                    let result = Self::attempt_download_chunk_task(link, i, &cloned_download_funcs)
+                    let result = Self::attempt_download_chunk_task(link.clone(), i, &cloned_download_funcs)
                         .await
                         .ok();
-                    tx_decrypt.send((i, result)).await.unwrap();
+                    let chunk = ReceivedChunk {
+                        i,
+                        data: match result {
+                            Some(data) => ChunkData::Downloaded(link, data),
+                            None => ChunkData::Failed,
+                        },
+                        window_permit,
+                    };
+                    // The receiver is gone if the download already failed
+                    let _ = tx_decrypt.send(chunk).await;
                 });
             }
         });
 
-        while let Some((i, data)) = rx_decrypt.recv().await {
-            if data.is_none() {
-                eprintln!("Error downloading chunk {}, empty message", i);
+        // Decryption and writing happen on up to MAX_DECRYPT_THREADS blocking workers, so the
+        // receive loop only hands chunks off. Waiting for a decrypt permit applies backpressure to
+        // the download tasks through the bounded channel. Workers release their decrypt permit
+        // before waiting for their turn to be hashed, since earlier chunks may still need one.
+        let decrypt_semaphore = self.decrypt_semaphore.clone();
+        let output_file = partial.as_ref().map(|partial| partial.file());
+        let mut decrypt_tasks: JoinSet<Result<Option<u64>, FilenSDKError>> = JoinSet::new();
+
+        let received: Result<(), FilenSDKError> = async {
+            let mut receiving = true;
+            while receiving || !decrypt_tasks.is_empty() {
+                tokio::select! {
+                    finished = decrypt_tasks.join_next(), if !decrypt_tasks.is_empty() => {
+                        if let Some(finished) = finished {
+                            if let Some(i) = finished?? {
+                                if let Some(partial) = &mut partial {
+                                    partial.mark_completed(i).await?;
+                                }
+                                remaining_chunks -= 1;
+                            }
+                        }
+                    }
+                    received = rx_decrypt.recv(), if receiving => {
+                        let Some(ReceivedChunk { i, data, window_permit }) = received else {
+                            receiving = false;
+                            continue;
+                        };
+
+                        let hasher = hasher.clone();
+                        let output_file = output_file.clone();
+                        let (link, data) = match data {
+                            ChunkData::Downloaded(link, data) => (link, data),
+                            ChunkData::Failed => {
+                                return Err(FilenSDKError::DownloadError {
+                                    err_str: format!("Error downloading chunk {}, empty message", i,),
+                                });
+                            }
+                            ChunkData::Resumed => {
+                                let (Some(hasher), Some(output_file)) = (hasher, output_file) else {
+                                    continue;
+                                };
+                                let len = std::cmp::min(CHUNK_SIZE as u64, file_size - i * CHUNK_SIZE as u64);
 
+                                decrypt_tasks.spawn_blocking(move || {
+                                    let mut data = vec![0; len as usize];
+                                    read_exact_at(&output_file, &mut data, i * CHUNK_SIZE as u64 - range_start)?;
+                                    hasher.update(i - start_chunk, &data);
+                                    drop(window_permit);
+                                    Ok(None)
+                                });
+                                continue;
+                            }
+                        };
+
+                        let permit = decrypt_semaphore.clone().acquire_owned().await.unwrap();
+                        let key = key.clone();
+                        let download_funcs = download_funcs.clone();
+                        let chunk_path = output_file_path.join(format!("{}", i));
+
+                        decrypt_tasks.spawn_blocking(move || {
+                            let (decrypted, range) = {
+                                let _permit = permit;
+                                let decrypted = Self::decrypt_chunk(&link, i, data, key.as_bytes(), &download_funcs)?;
+                                let range = chunk_slice(i, decrypted.len(), range_start, range_end);
+                                let offset = i * CHUNK_SIZE as u64 + range.start as u64 - range_start;
+
+                                match output_file {
+                                    Some(file) => write_output_at(&file, &decrypted[range.clone()], offset)?,
+                                    None => write_output(&chunk_path, &decrypted[range.clone()], None)?,
+                                }
+                                (decrypted, range)
+                            };
+
+                            if let Some(hasher) = hasher {
+                                hasher.update(i - start_chunk, &decrypted[range]);
+                            }
+                            drop(window_permit);
+                            Ok(Some(i))
+                        });
+                    }
+                }
+            }
+
+            // Every chunk has to be accounted for before the output may appear under its final name
+            if remaining_chunks > 0 {
                 return Err(FilenSDKError::DownloadError {
-                    err_str: format!("Error downloading chunk {}, empty message", i,),
+                    err_str: format!("Download ended with {} chunks missing", remaining_chunks),
                 });
             }
+            Ok(())
+        }
+        .await;
 
-            let decrypt_in_memory =
-                Self::decrypt_chunk_task(i, data.unwrap(), &key.as_bytes(), &download_funcs)
-                    .await?;
+        if let Err(err) = received {
+            dispatcher.abort();
+            drop(rx_decrypt);
 
-            if let Some(output_name) = &output_name {
-                let out_path = output_file_path.join(output_name);
-                write_output_async(
-                    &out_path,
-                    &decrypt_in_memory,
-                    Some((i - start_chunk) as usize),
-                )
-                .await?;
-            } else {
-                let out_path = output_file_path.join(format!("{}", i));
-                write_output_async(&out_path, &decrypt_in_memory, None).await?;
+            // Workers waiting for a chunk that will never be hashed give up
+            if let Some(hasher) = &hasher {
+                hasher.fail();
             }
+
+            // Blocking writes can't be cancelled, so wait for them before the partial file is
+            // left to the next attempt. Chunks that made it are kept for that attempt.
+            while let Some(finished) = decrypt_tasks.join_next().await {
+                if let (Ok(Ok(Some(i))), Some(partial)) = (finished, &mut partial) {
+                    partial.mark_completed(i).await?;
+                }
+            }
+            if let Some(partial) = &mut partial {
+                partial.save_progress().await?;
+            }
+            return Err(err);
         }
 
-        Ok((
-            start_chunk * crate::crypto::CHUNK_SIZE as u64,
-            std::cmp::min(end_chunk * crate::crypto::CHUNK_SIZE as u64, file_size),
-        ))
+        // The output is only published if it matches the metadata. A mismatching partial file is
+        // discarded, resuming it would only fail again.
+        if let Some(hasher) = hasher {
+            if let Err(err) = hasher.take().verify_parts(&uuid, file_size, hash.as_deref()) {
+                if let Some(partial) = partial {
+                    partial.discard().await?;
+                }
+                return Err(err);
+            }
+        }
+
+        if let Some(partial) = partial {
+            partial.finish().await?;
+        }
+
+        Ok((range_start, range_end))
+    }
+
+    /// Downloads the chunks covering `byte_range_start..byte_range_end` in parallel, but yields
+    /// their plaintext strictly in order, trimmed to exactly the requested range.
+    ///
+    /// Chunks that finish early wait in a reorder buffer until every chunk before them has been
+    /// yielded. At most MAX_REORDER_CHUNKS chunks are in flight or buffered at once, so memory
+    /// stays bounded even when the consumer is slow.
+    pub fn ordered_download_stream<T>(
+        &self,
+        source: ChunkSource,
+        byte_range_start: u64,
+        byte_range_end: u64,
+        download_funcs: impl FilenNetInteractionFunctions<T>,
+    ) -> impl Stream<Item = Result<Bytes, FilenSDKError>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let ChunkSource {
+            uuid,
+            region,
+            bucket,
+            key,
+            size: file_size,
+            ..
+        } = source;
+        let (start_chunk, end_chunk) =
+            calculate_chunk_range(byte_range_start, byte_range_end, file_size);
+        let byte_range_end = std::cmp::min(byte_range_end, file_size);
+
+        let download_semaphore = self.download_semaphore.clone();
+        let decrypt_semaphore = self.decrypt_semaphore.clone();
+
+        futures::stream::iter(start_chunk..end_chunk)
+            .map(move |i| {
+                let link = FsURL::Egest(region.clone(), bucket.clone(), uuid.clone(), i);
+                let key = key.clone();
+                let download_funcs = download_funcs.clone();
+                let download_semaphore = download_semaphore.clone();
+                let decrypt_semaphore = decrypt_semaphore.clone();
+
+                // Spawned so that chunks keep downloading while the consumer is busy
+                tokio::spawn(async move {
+                    let decrypted = Self::fetch_plaintext_chunk(
+                        link,
+                        i,
+                        key,
+                        download_funcs,
+                        download_semaphore,
+                        decrypt_semaphore,
+                    )
+                    .await?;
+
+                    let range = chunk_slice(i, decrypted.len(), byte_range_start, byte_range_end);
+                    Ok::<Bytes, FilenSDKError>(decrypted.slice(range))
+                })
+            })
+            .buffered(MAX_REORDER_CHUNKS)
+            .map(|joined| joined?)
     }
 
     async fn summon_single_download_decrypt_task(
         i: u64,
         link: FsURL,
         client: Arc<reqwest::Client>,
+        chunk_cache: Arc<EncryptedChunkCache>,
         key: String,
-    ) -> Option<Bytes> {
+    ) -> Result<Bytes, FilenSDKError> {
         let download_method = LowDiskInteractionFunctions {
             client: client.clone(),
             api_key: "".to_string(),
             should_use_counter_nonce: false,
+            chunk_cache: Some(chunk_cache),
         };
-        let downloaded_bytes = FilenSDK::attempt_download_chunk_task(link, i, &download_method)
-            .await
-            .ok();
-        match downloaded_bytes {
-            Some(bytes) => {
-                let mut prepared_bytes_to_decrypt = download_method.decrypt_retrieve_data(bytes);
-                let decrypted_bytes =
-                    decrypt_v2_bytes(&mut prepared_bytes_to_decrypt, key.as_bytes());
-
-                match decrypted_bytes {
-                    Ok(bytes) => Some(bytes.freeze()),
-                    Err(e) => {
-                        eprintln!("Error decrypting chunk: {:?}", e);
-                        None
-                    }
-                }
-            }
-            None => None,
-        }
+        let bytes = FilenSDK::attempt_download_chunk_task(link.clone(), i, &download_method).await?;
+        let mut prepared_bytes_to_decrypt = download_method.decrypt_retrieve_data(bytes);
+        let decrypted_bytes = decrypt_v2_bytes(&mut prepared_bytes_to_decrypt, key.as_bytes())
+            .inspect_err(|_| download_method.discard_retrieved_data(&link, i))?;
+
+        Ok(decrypted_bytes.freeze())
     }
 
     // TODO: Allow for custom download functions
     /// Stream downloaded chunks, this method is sensitive to the order of the chunks and will not continue until the previous chunk is downloaded.
-    /// However, it will look ahead and download the next MAX_READ_AHEAD_THREADS chunks in parallel.
+    /// Yields the plaintext of `start_byte..end_byte`, with `end_byte` clamped to the file size.
+    ///
+    /// Upcoming chunks are downloaded in parallel within an adaptive read-ahead window (see
+    /// `ReadAheadWindow`). It starts at MIN_READ_AHEAD_CHUNKS, grows up to MAX_READ_AHEAD_THREADS
+    /// when the consumer reads faster than chunks arrive, and shrinks while read-ahead buffers
+    /// across the SDK exceed MAX_READ_AHEAD_MEMORY. Dropping the stream, e.g. to reposition it,
+    /// aborts every queued chunk.
     pub fn read_ahead_download_stream(
         &self,
-        size: u64,
+        source: ChunkSource,
         start_byte: u64,
-        region: String,
-        bucket: String,
-        uuid: String,
-        key: String,
+        end_byte: u64,
     ) -> impl Stream<Item = Result<Bytes, FilenSDKError>> {
-        let total_chunks = size / (CHUNK_SIZE as u64) + 1;
+        let ChunkSource {
+            uuid,
+            region,
+            bucket,
+            key,
+            size,
+            ..
+        } = source;
+        let end_byte = std::cmp::min(end_byte, size);
+        let total_chunks = end_byte.div_ceil(CHUNK_SIZE as u64);
         let start_chunk = start_byte / (CHUNK_SIZE as u64);
 
         let client = self.client.clone();
+        let chunk_cache = self.chunk_cache.clone();
+        let read_ahead_bytes = self.read_ahead_bytes.clone();
         async_stream::stream! {
             let mut current_chunk = start_chunk;
-            let mut task_deque: VecDeque<JoinHandle<Option<Bytes>>> = VecDeque::new();
+            let mut next_chunk = start_chunk;
+            let mut window = ReadAheadWindow::new();
+            let mut task_deque: VecDeque<ReadAheadTask<Result<Bytes, FilenSDKError>>> = VecDeque::new();
+            let mut last_yield = Instant::now();
 
-            let top_chunk = std::cmp::min(start_chunk + MAX_READ_AHEAD_THREADS + 1, total_chunks);
-            for i in start_chunk..top_chunk {
-                println!("Starting chunk {} for start byte {} and size {}", i, start_byte, size);
-                let link = crate::httpclient::FsURL::Egest(
-                    region.to_string(),
-                    bucket.to_string(),
-                    uuid.to_string(),
-                    i,
-                );
-
-                task_deque.push_back(tokio::spawn(Self::summon_single_download_decrypt_task(i, link, client.clone(), key.clone())));
-            }
-
-            loop {
-                if current_chunk >= total_chunks {
-                    break;
+            while current_chunk < total_chunks {
+                if read_ahead_bytes.load(Ordering::Relaxed) > MAX_READ_AHEAD_MEMORY {
+                    window.shrink();
                 }
 
-                let result = task_deque.pop_front();
-                let data = result.unwrap().await.unwrap();
-                if data.is_none() {
-                    eprintln!("Error downloading chunk, empty message");
-
-                    break;
-                }
-
-                // If first chunk, then start from offset of start_byte
-                let start_offset = if current_chunk == start_chunk {
-                    let start_offset = start_byte % (CHUNK_SIZE as u64);
-                    println!("Starting from offset {}", start_offset);
-                    start_offset
-                } else {
-                    0
-                };
-                // yield Ok(hyper::body::Frame::data(data.unwrap().slice(start_offset as usize..)));
-                yield Ok(data.unwrap().slice(start_offset as usize..));
-
-
-                current_chunk += 1;
-                if current_chunk < total_chunks {
+                while next_chunk < total_chunks && (task_deque.len() as u64) < window.size() {
                     let link = crate::httpclient::FsURL::Egest(
                         region.to_string(),
                         bucket.to_string(),
                         uuid.to_string(),
-                        current_chunk + MAX_READ_AHEAD_THREADS,
+                        next_chunk,
                     );
 
-                    task_deque.push_back(tokio::spawn(Self::summon_single_download_decrypt_task(current_chunk + MAX_READ_AHEAD_THREADS, link, client.clone(), key.clone())));
+                    task_deque.push_back(ReadAheadTask::spawn(
+                        Self::summon_single_download_decrypt_task(next_chunk, link, client.clone(), chunk_cache.clone(), key.clone()),
+                        read_ahead_bytes.clone(),
+                    ));
+                    next_chunk += 1;
                 }
+
+                let interval = last_yield.elapsed();
+                let joined = task_deque.pop_front().unwrap().join().await;
+
+                // Stop at the first failed chunk, consumers treat the end of the stream as the
+                // end of the data
+                let data = match joined {
+                    Ok((Ok(data), latency)) => {
+                        window.record(latency, interval);
+                        data
+                    }
+                    Ok((Err(err), _)) => {
+                        yield Err(err);
+                        break;
+                    }
+                    Err(err) => {
+                        yield Err(err.into());
+                        break;
+                    }
+                };
+
+                // The first and last chunks are trimmed to start_byte and end_byte
+                yield Ok(data.slice(chunk_slice(current_chunk, data.len(), start_byte, end_byte)));
+                last_yield = Instant::now();
+
+                current_chunk += 1;
             }
         }
     }
 }
+
+/// Part of chunk `i`, holding `len` bytes of plaintext, that lies within
+/// `range_start..range_end` of the file
+fn chunk_slice(i: u64, len: usize, range_start: u64, range_end: u64) -> std::ops::Range<usize> {
+    let chunk_start = i * CHUNK_SIZE as u64;
+    let to = std::cmp::min(range_end.saturating_sub(chunk_start), len as u64) as usize;
+    let from = std::cmp::min(range_start.saturating_sub(chunk_start) as usize, to);
+    from..to
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use futures::TryStreamExt;
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::{
+        crypto::generate_rand_key,
+        mod_private::{
+            download_manifest::{sidecar_path, MANIFEST_EXTENSION},
+            net_interaction::mock::MockInteractionFunctions,
+            partial_download::PARTIAL_EXTENSION,
+        },
+    };
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn source(key: &[u8; 32], size: u64) -> ChunkSource {
+        ChunkSource {
+            uuid: "uuid".to_string(),
+            region: "region".to_string(),
+            bucket: "bucket".to_string(),
+            key: String::from_utf8(key.to_vec()).unwrap(),
+            size,
+            hash: None,
+        }
+    }
+
+    /// Options writing `start..end` to a single output file named `name`
+    fn options(name: &str, start: u64, end: u64, exact_range: bool) -> OrderlessDownloadOptions {
+        OrderlessDownloadOptions {
+            output_name: Some(name.to_string()),
+            start_byte: start,
+            end_byte: Some(end),
+            exact_range,
+            verify: false,
+        }
+    }
+
+    fn sha512(data: &[u8]) -> String {
+        hex::encode(ring::digest::digest(&ring::digest::SHA512, data))
+    }
+
+    /// Downloads the whole of `data` to `output`, verified against its hash
+    async fn mock_download(sdk: &FilenSDK, mock: MockInteractionFunctions, key: &[u8; 32], data: &[u8], output: &Path) {
+        sdk.orderless_file_download(
+            ChunkSource {
+                hash: Some(sha512(data)),
+                ..source(key, data.len() as u64)
+            },
+            output.parent().unwrap(),
+            OrderlessDownloadOptions {
+                output_name: Some(output.file_name().unwrap().to_str().unwrap().to_string()),
+                verify: true,
+                ..Default::default()
+            },
+            mock,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orderless_download_mock() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 3 + CHUNK_SIZE / 2);
+        let output = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        let key = generate_rand_key().unwrap();
+        let mock = MockInteractionFunctions::from_plaintext(&data, &key);
+
+        mock_download(&sdk, mock, &key, &data, &output).await;
+
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orderless_download_resume() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 4 + 10);
+        let size = data.len() as u64;
+        let key = generate_rand_key().unwrap();
+        let mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        let output_dir = std::env::temp_dir();
+        let name = format!("{}.bin", uuid::Uuid::new_v4());
+        let download = |mock: MockInteractionFunctions| {
+            sdk.orderless_file_download(source(&key, size), &output_dir, options(&name, 0, size, false), mock)
+        };
+
+        // A missing chunk fails the download once the chunks in flight have been written
+        let missing = mock.chunks.lock().unwrap().remove(&2).unwrap();
+        assert!(download(mock.clone()).await.is_err());
+        assert!(!output_dir.join(&name).exists());
+
+        // The next attempt only needs the chunk that failed
+        mock.chunks.lock().unwrap().clear();
+        mock.chunks.lock().unwrap().insert(2, missing);
+        download(mock).await.unwrap();
+
+        assert_eq!(std::fs::read(output_dir.join(&name)).unwrap(), data);
+        std::fs::remove_file(output_dir.join(&name)).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ordered_download_stream() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 3 + 100);
+        let key = generate_rand_key().unwrap();
+        let mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        let size = data.len() as u64;
+
+        let whole: Vec<Bytes> = sdk
+            .ordered_download_stream(source(&key, size), 0, size, mock.clone())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(whole.len(), 4);
+        assert_eq!(whole.concat(), data);
+
+        // Ranges are trimmed to the exact bytes, even within and across chunk boundaries
+        for (start, end) in [(10, 20), (CHUNK_SIZE - 5, CHUNK_SIZE * 2 + 5), (CHUNK_SIZE * 3, data.len())] {
+            let range: Vec<Bytes> = sdk
+                .ordered_download_stream(source(&key, size), start as u64, end as u64, mock.clone())
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(range.concat(), &data[start..end]);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orderless_download_exact_range() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 3 + 100);
+        let size = data.len() as u64;
+        let key = generate_rand_key().unwrap();
+        let mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        let output_dir = std::env::temp_dir();
+
+        let ranges = [
+            (10, 20),
+            (CHUNK_SIZE as u64 - 5, CHUNK_SIZE as u64 * 2 + 5),
+            (CHUNK_SIZE as u64 * 3 + 1, size + 50),
+            (7, 7),
+        ];
+        for (start, end) in ranges {
+            let name = format!("{}.bin", uuid::Uuid::new_v4());
+            let written = sdk
+                .orderless_file_download(source(&key, size), &output_dir, options(&name, start, end, true), mock.clone())
+                .await
+                .unwrap();
+
+            // End exclusive, clamped to the file size
+            let end = std::cmp::min(end, size);
+            assert_eq!(written, (start, end));
+
+            let output = output_dir.join(&name);
+            assert_eq!(std::fs::read(&output).unwrap(), &data[start as usize..end as usize]);
+            std::fs::remove_file(&output).unwrap();
+        }
+
+        // Chunk-aligned mode rounds out to whole chunks
+        let name = format!("{}.bin", uuid::Uuid::new_v4());
+        let written = sdk
+            .orderless_file_download(source(&key, size), &output_dir, options(&name, 10, CHUNK_SIZE as u64 + 10, false), mock.clone())
+            .await
+            .unwrap();
+        assert_eq!(written, (0, CHUNK_SIZE as u64 * 2));
+        assert_eq!(std::fs::read(output_dir.join(&name)).unwrap(), &data[..CHUNK_SIZE * 2]);
+        std::fs::remove_file(output_dir.join(&name)).unwrap();
+
+        let inverted = sdk
+            .orderless_file_download(source(&key, size), &output_dir, options(&name, 20, 10, true), mock)
+            .await;
+        assert!(matches!(inverted, Err(FilenSDKError::InvalidByteRange { .. })));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orderless_download_verify() {
+        let sdk = FilenSDK::new();
+        let data = plaintext(CHUNK_SIZE * 4 + 10);
+        let size = data.len() as u64;
+        let key = generate_rand_key().unwrap();
+        let mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        let output_dir = std::env::temp_dir();
+        let name = format!("{}.bin", uuid::Uuid::new_v4());
+        let output = output_dir.join(&name);
+        let partial = sidecar_path(&output, PARTIAL_EXTENSION);
+        let manifest = sidecar_path(&output, MANIFEST_EXTENSION);
+        let download = |expected: &[u8], mock: MockInteractionFunctions| {
+            let source = ChunkSource {
+                hash: Some(sha512(expected)),
+                ..source(&key, size)
+            };
+            let options = OrderlessDownloadOptions {
+                verify: true,
+                ..options(&name, 0, size, false)
+            };
+            sdk.orderless_file_download(source, &output_dir, options, mock)
+        };
+
+        // A mismatch publishes nothing and leaves nothing to resume
+        let result = download(b"other", mock.clone()).await;
+        assert!(matches!(result, Err(FilenSDKError::IntegrityError { .. })));
+        assert!(!output.exists() && !partial.exists() && !manifest.exists());
+
+        // Chunks resumed from a previous attempt are hashed along with the downloaded ones
+        let missing = mock.chunks.lock().unwrap().remove(&2).unwrap();
+        assert!(download(&data, mock.clone()).await.is_err());
+        mock.chunks.lock().unwrap().insert(2, missing.clone());
+        download(&data, mock.clone()).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        std::fs::remove_file(&output).unwrap();
+
+        // So a resumed chunk corrupted on disk is caught
+        mock.chunks.lock().unwrap().remove(&2);
+        assert!(download(&data, mock.clone()).await.is_err());
+        write_output_at(&std::fs::File::options().write(true).open(&partial).unwrap(), b"x", 5).unwrap();
+        mock.chunks.lock().unwrap().insert(2, missing);
+        let result = download(&data, mock).await;
+        assert!(matches!(result, Err(FilenSDKError::IntegrityError { .. })));
+        assert!(!output.exists() && !partial.exists() && !manifest.exists());
+    }
+
+    /// Chunks are decrypted by several workers at once, never more than the decrypt semaphore
+    /// allows, and land at their own offset whatever order they finish in.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orderless_decrypt_pool() {
+        let data = plaintext(CHUNK_SIZE * 16 + 7);
+        let output = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        let key = generate_rand_key().unwrap();
+        let mut mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        mock.decrypt_delay = Some(Duration::from_millis(20));
+
+        let mut sdk = FilenSDK::new();
+        sdk.decrypt_semaphore = Arc::new(Semaphore::new(3));
+        mock_download(&sdk, mock.clone(), &key, &data, &output).await;
+
+        let max_decrypts = mock.max_decrypts.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(max_decrypts > 1, "chunks were decrypted one at a time");
+        assert!(max_decrypts <= 3, "{} chunks were decrypted at once", max_decrypts);
+
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    /// Compares the decrypt pool with decrypting one chunk at a time, like downloads did before
+    /// the pool. Run with `cargo test --release bench_orderless_decrypt_pool -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_orderless_decrypt_pool() {
+        let data = plaintext(CHUNK_SIZE * 64);
+        let key = generate_rand_key().unwrap();
+        let mut mock = MockInteractionFunctions::from_plaintext(&data, &key);
+        // Stands in for the cost of decrypting a chunk, the mock itself does not decrypt
+        mock.decrypt_delay = Some(Duration::from_millis(10));
+
+        let mut timings = Vec::new();
+        for permits in [1, crate::filensdk::MAX_DECRYPT_THREADS] {
+            let mut sdk = FilenSDK::new();
+            sdk.decrypt_semaphore = Arc::new(Semaphore::new(permits));
+            let output = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+
+            let started = std::time::Instant::now();
+            mock_download(&sdk, mock.clone(), &key, &data, &output).await;
+            let elapsed = started.elapsed();
+            std::fs::remove_file(&output).unwrap();
+
+            println!("{} decrypt permits: {:?}", permits, elapsed);
+            timings.push(elapsed);
+        }
+
+        assert!(timings[1] < timings[0], "the decrypt pool was not faster than decrypting one chunk at a time");
+    }
+}
@@ -1,22 +1,83 @@
-use std::{fs::File, hash, sync::Arc};
+use std::{fs::File, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Instant, SystemTime}};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::{
-    crypto::CHUNK_SIZE, download, error::FilenSDKError, filensdk::MAX_UPLOAD_THREADS, mod_private::net_interaction::FilenNetInteractionFunctions, requests::fs::FileMetadata, responses::fs::UploadChunkResponse, FilenSDK
+    crypto::{file_encrypt::encrypt_v2_bytes, CHUNK_SIZE}, error::FilenSDKError, filensdk::{MAX_ENCRYPT_THREADS, MAX_UPLOAD_THREADS}, mod_private::{integrity::{OrderedHasher, PlaintextHasher}, net_interaction::FilenNetInteractionFunctions, upload::ConflictResolution}, file::FilenFileDetailed, requests::fs::FileMetadata, responses::{auth::AuthVersion, fs::UploadChunkResponse}, upload::{FilenUploadResult, UploadConflictPolicy}, FilenSDK
 };
 
 use super::FsURL;
 
+/// A file whose chunks have all been uploaded, see `finish_upload`
+pub struct UploadedFile {
+    pub uuid: String,
+    pub upload_key: String,
+    pub filen_parent: String,
+    pub name: String,
+    pub mime: String,
+    pub key: [u8; 32],
+    pub size: u64,
+    pub chunks: u64,
+    pub last_modified: SystemTime,
+    /// SHA-512 hex digest of the plaintext
+    pub hash: String,
+    /// Where the ingest servers stored the chunks, `None` for a file without chunks
+    pub location: Option<UploadChunkResponse>,
+}
+
+impl UploadedFile {
+    /// Metadata stored for the file. The modification time is in milliseconds, like the official
+    /// clients store it.
+    pub(crate) fn metadata(&self) -> FileMetadata {
+        let last_modified = self
+            .last_modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        FileMetadata {
+            name: self.name.clone(),
+            size: Some(self.size),
+            mime: Some(self.mime.clone()),
+            key: self.key.to_vec(),
+            last_modified: Some(last_modified),
+            hash: Some(self.hash.clone()),
+        }
+    }
+}
+
+/// File whose chunks `upload_chunks_from_reader` uploads
+pub struct ChunkUploadTarget<'a> {
+    pub uuid: &'a str,
+    pub upload_key: &'a str,
+    pub filen_parent: &'a str,
+    /// Key the chunks are encrypted with
+    pub key: &'a [u8; 32],
+}
+
+/// What `upload_chunks_from_reader` read and uploaded
+pub struct UploadedChunks {
+    pub size: u64,
+    pub chunks: u64,
+    pub hash: String,
+    pub location: Option<UploadChunkResponse>,
+}
+
 impl FilenSDK {
     pub async fn upload_file_generic<T>(
         &self,
         input_file: &str,
         filen_parent: &str,
         name: &str,
+        conflict_policy: UploadConflictPolicy,
         download_funcs: impl FilenNetInteractionFunctions<T>,
-    ) -> Result<String, FilenSDKError>
+    ) -> Result<FilenUploadResult, FilenSDKError>
     where
         T: Send + Sync + 'static,
     {
+        let started = Instant::now();
+
         // Does file exist?
         if !std::path::Path::new(&input_file).exists() {
             return Err(FilenSDKError::FileDoesNotExist {
@@ -34,70 +95,71 @@ impl FilenSDK {
             .first_or_octet_stream()
             .to_string();
 
+        // Resolve name conflicts before anything is sent
+        let file_name = match self
+            .resolve_upload_conflict(filen_parent, name, conflict_policy)
+            .await?
+        {
+            ConflictResolution::Upload { name } => name,
+            ConflictResolution::Skip(existing) => return self.skipped_upload(existing, started).await,
+        };
+
         // Generate shared key used for encryption
         let key = crate::crypto::generate_rand_key()?;
 
         let uuid = uuid::Uuid::new_v4().to_string();
-        let file_name = name.to_string();
 
         // Calculate number of chunks there will be
         let chunks = (file_size as f64 / CHUNK_SIZE as f64).ceil() as usize;
 
-        // Create metadata
-        let metadata = FileMetadata {
-            name: file_name.clone(),
-            size: Some(file_size),
-            mime: Some(mime.clone()),
-            key: key.to_vec(),
-            last_modified: Some(
-                last_modified
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64,
-            ),
-            hash: None,
-        };
-
-        let key_str = String::from_utf8(key.to_vec()).unwrap();
-
-        // Encrypt metadata
-        let name_enc = crate::crypto::metadata::encrypt_metadata(&file_name.as_bytes(), &key_str)?;
-        let mime_enc = crate::crypto::metadata::encrypt_metadata(&mime.as_bytes(), &key_str)?;
-        let name_hashed = crate::crypto::metadata::hash_fn(&file_name.to_lowercase())?;
-        let size_enc =
-            crate::crypto::metadata::encrypt_metadata(&file_size.to_string().as_bytes(), &key_str)?;
-        let metadata_json =
-            serde_json::to_string(&metadata).map_err(|_| FilenSDKError::SerdeJsonError {
-                err_msg: "Failed to serialize metadata".to_string(),
-                err_str: "".to_string(),
-            })?;
-        let metadata_enc =
-            crate::crypto::metadata::encrypt_metadata(&metadata_json.as_bytes(), &self.master_key()?)?;
+        // Tokio channel for sending chunks, which bounds the encrypted chunks held in memory
+        let (tx, mut rx) =
+            tokio::sync::mpsc::channel::<Result<(usize, (T, String)), FilenSDKError>>(MAX_UPLOAD_THREADS);
 
-        // Tokio channel for sending chunks
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<(usize, (T, String))>(MAX_UPLOAD_THREADS);
+        // Start encrypt workers, which claim chunks in increasing order and read them with
+        // positional reads from the shared handle. The plaintext is hashed on the way.
+        let file = Arc::new(file);
+        let next_chunk = Arc::new(AtomicUsize::new(0));
+        let hasher = Arc::new(OrderedHasher::new());
+        for _ in 0..std::cmp::min(MAX_ENCRYPT_THREADS, chunks) {
+            let tx = tx.clone();
+            let file = file.clone();
+            let next_chunk = next_chunk.clone();
+            let hasher = hasher.clone();
+            let download_funcs = download_funcs.clone();
+            tokio::task::spawn_blocking(move || loop {
+                let i = next_chunk.fetch_add(1, Ordering::Relaxed);
+                if i >= chunks {
+                    break;
+                }
 
-        // Start encrypt thread
-        let input_file_clone = input_file.to_string();
-        let download_funcs_clone = download_funcs.clone();
-        tokio::spawn(async move {
-            for i in 0..chunks {
-                // let data = crate::crypto::file_encrypt::encrypt_v2_from_file(&input_file_clone, None, &key, i).unwrap();
-                let data = download_funcs_clone.encrypt_data(&input_file_clone, i as u64, &key);
-                tx.send((i, data)).await.unwrap();
-            }
-        });
+                let data = download_funcs
+                    .encrypt_data(&file, i as u64, &key, Some(&hasher))
+                    .map(|data| (i, data));
+                let failed = data.is_err();
+                // The receiver is gone once the upload failed, so stop encrypting
+                if tx.blocking_send(data).is_err() || failed {
+                    hasher.fail();
+                    break;
+                }
+            });
+        }
+        drop(tx);
 
         let upload_key =
             String::from_utf8(crate::crypto::generate_rand_key().unwrap().to_vec()).unwrap();
 
         // Start upload threads
-        let (tx_upload, mut rx_upload) = tokio::sync::mpsc::channel::<(usize, bool)>(MAX_UPLOAD_THREADS);
+        let (tx_upload, mut rx_upload) =
+            tokio::sync::mpsc::channel::<Result<UploadChunkResponse, FilenSDKError>>(MAX_UPLOAD_THREADS);
+        let mut location = None;
         for i in 0..chunks {
-            let (index, data) = rx.recv().await.unwrap();
+            let (index, data) = rx.recv().await.ok_or_else(|| FilenSDKError::UploadError {
+                err_str: "Encryption workers stopped early".to_string(),
+            })??;
             if i > MAX_UPLOAD_THREADS {
-                let resp = rx_upload.recv().await.unwrap();
-                crate::return_function_on_result_fail!(resp);
+                let response = rx_upload.recv().await.unwrap()?;
+                location = location.or(Some(response));
             }
 
             let tx_upload = tx_upload.clone();
@@ -122,36 +184,304 @@ impl FilenSDK {
                 );
                 let response = download_funcs.http_upload_data(url, data).await;
 
-                match response {
-                    Ok(_) => {
-                        tx_upload.send((index, true)).await.unwrap();
-                    }
-                    Err(e) => {
-                        tx_upload.send((index, false)).await.unwrap();
-                    }
-                }
+                // The receiver is gone once another chunk failed
+                let _ = tx_upload.send(response).await;
             });
         }
 
         drop(tx_upload);
 
-        while let Some(resp) = rx_upload.recv().await {
-            crate::return_function_on_result_fail!(resp);
+        while let Some(response) = rx_upload.recv().await {
+            location = location.or(Some(response?));
+        }
+        // Every chunk is hashed before it is sent, so the hash is complete once all were received
+        let hash = hasher.finish();
+
+        self.finish_upload(
+            UploadedFile {
+                uuid,
+                upload_key,
+                filen_parent: filen_parent.to_string(),
+                name: file_name,
+                mime,
+                key,
+                size: file_size,
+                chunks: chunks as u64,
+                last_modified,
+                hash,
+                location,
+            },
+            started,
+        )
+        .await
+    }
+
+    /// Reads `reader` chunk by chunk until it ends, encrypting every chunk with the key of
+    /// `target` and uploading it as part of that file. Chunks are encrypted while earlier ones
+    /// are still uploading, with at most MAX_UPLOAD_THREADS uploads in flight.
+    ///
+    /// `size_hint` only sizes the first read buffer, the reader may end up longer or shorter.
+    /// Returns the plaintext size, the number of chunks uploaded and the plaintext hash.
+    pub async fn upload_chunks_from_reader<R>(
+        &self,
+        reader: &mut R,
+        target: &ChunkUploadTarget<'_>,
+        size_hint: Option<u64>,
+        upload_funcs: impl FilenNetInteractionFunctions<Bytes>,
+    ) -> Result<UploadedChunks, FilenSDKError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let should_use_counter_nonce = upload_funcs.should_use_counter_nonce();
+        let mut buffer = vec![0; size_hint.map_or(CHUNK_SIZE, |hint| std::cmp::min(hint as usize, CHUNK_SIZE))];
+        let mut uploads = tokio::task::JoinSet::new();
+        let mut hasher = PlaintextHasher::new();
+        let mut location = None;
+        let mut size = 0;
+        let mut chunks = 0;
+
+        loop {
+            let read = read_chunk(reader, &mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+
+            let key = *target.key;
+            let index = chunks as usize;
+            let (returned_buffer, encrypted) = tokio::task::spawn_blocking(move || {
+                let encrypted = encrypt_v2_bytes(&buffer[..read], &key, index, should_use_counter_nonce);
+                (buffer, encrypted)
+            })
+            .await?;
+            buffer = returned_buffer;
+            let (data, hash) = encrypted?;
+
+            while uploads.len() >= MAX_UPLOAD_THREADS {
+                let response = uploads.join_next().await.unwrap()??;
+                location = location.or(Some(response));
+            }
+
+            let url = FsURL::Igest(
+                target.uuid.to_string(),
+                target.upload_key.to_string(),
+                chunks,
+                target.filen_parent.to_string(),
+                hash,
+            );
+            let upload_funcs = upload_funcs.clone();
+            let upload_semaphore = self.upload_semaphore.clone();
+            uploads.spawn(async move {
+                let _permit = upload_semaphore.acquire().await.unwrap();
+                upload_funcs.http_upload_data(url, data).await
+            });
+
+            size += read as u64;
+            chunks += 1;
+            if read < CHUNK_SIZE {
+                // A short chunk means the reader has ended
+                break;
+            }
+        }
+
+        // Dropping the set on an error aborts the remaining uploads
+        while let Some(result) = uploads.join_next().await {
+            let response = result??;
+            location = location.or(Some(response));
         }
 
+        Ok(UploadedChunks {
+            size,
+            chunks,
+            hash: hasher.finish(),
+            location,
+        })
+    }
+
+    /// Completes an upload whose chunks were all sent: encrypts the file metadata and marks the
+    /// upload as done. If a file with the same name exists, the server keeps it as a previous
+    /// version. The hash is stored in the metadata like the official clients do.
+    ///
+    /// The returned details are built from what the upload already knows, without asking the
+    /// server again. `started` is when the upload began.
+    pub async fn finish_upload(
+        &self,
+        file: UploadedFile,
+        started: Instant,
+    ) -> Result<FilenUploadResult, FilenSDKError> {
+        // Create metadata
+        let metadata = file.metadata();
+        let last_modified = metadata.last_modified;
+
+        let key_str = String::from_utf8(file.key.to_vec()).unwrap();
+
+        // Encrypt metadata
+        let name_enc = crate::crypto::metadata::encrypt_metadata(file.name.as_bytes(), &key_str)?;
+        let mime_enc = crate::crypto::metadata::encrypt_metadata(file.mime.as_bytes(), &key_str)?;
+        let name_hashed = crate::crypto::metadata::hash_fn(&file.name.to_lowercase())?;
+        let size_enc =
+            crate::crypto::metadata::encrypt_metadata(file.size.to_string().as_bytes(), &key_str)?;
+        let metadata_json =
+            serde_json::to_string(&metadata).map_err(|_| FilenSDKError::SerdeJsonError {
+                err_msg: "Failed to serialize metadata".to_string(),
+                err_str: "".to_string(),
+            })?;
+        let metadata_enc =
+            crate::crypto::metadata::encrypt_metadata(metadata_json.as_bytes(), &self.master_key()?)?;
+
         // Mark upload as done
         self.mark_upload_as_done(
-            uuid.clone(),
+            file.uuid.clone(),
             String::from_utf8(name_enc).unwrap(),
             name_hashed,
             String::from_utf8(size_enc).unwrap(),
-            chunks as i64,
+            file.chunks as i64,
             String::from_utf8(mime_enc).unwrap(),
-            "false".to_string(),
+            // Random like the official clients send it
+            String::from_utf8(crate::crypto::generate_rand_key()?.to_vec()).unwrap(),
             String::from_utf8(metadata_enc).unwrap(),
-            upload_key
+            file.upload_key
         ).await?;
 
-        Ok(uuid)
+        let (region, bucket) = file
+            .location
+            .map_or_else(Default::default, |location| (location.region, location.bucket));
+        Ok(FilenUploadResult {
+            file: FilenFileDetailed {
+                uuid: file.uuid,
+                region,
+                bucket,
+                name: file.name,
+                size: file.size,
+                mime: file.mime,
+                key: file.key.to_vec(),
+                last_modified,
+                hash: Some(file.hash.clone()),
+                parent: file.filen_parent,
+                versioned: None,
+                trash: false,
+                version: AuthVersion::V2,
+            },
+            chunks: file.chunks,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            hash: Some(file.hash),
+            skipped: false,
+        })
+    }
+
+    /// Result of an upload that was skipped because of the existing file `uuid`
+    pub async fn skipped_upload(
+        &self,
+        uuid: String,
+        started: Instant,
+    ) -> Result<FilenUploadResult, FilenSDKError> {
+        let file = self.file_info(uuid).await?;
+
+        Ok(FilenUploadResult {
+            chunks: file.size.div_ceil(CHUNK_SIZE as u64),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            hash: file.hash.clone(),
+            skipped: true,
+            file,
+        })
+    }
+}
+
+/// Reads from `reader` until `buffer` holds a whole chunk or the reader ends, returning the
+/// number of bytes read. A buffer smaller than CHUNK_SIZE grows if the reader has more to give.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+    let mut filled = 0;
+    loop {
+        if filled == buffer.len() {
+            if filled >= CHUNK_SIZE {
+                break;
+            }
+            buffer.resize(CHUNK_SIZE, 0);
+        }
+
+        match reader.read(&mut buffer[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::{
+        crypto::{file_decrypt::decrypt_v2_bytes, generate_rand_key},
+        mod_private::net_interaction::mock::MockInteractionFunctions,
+    };
+
+    /// An uploaded file last modified at `last_modified`, for checking what the upload path
+    /// stores against the code reading it back
+    pub(crate) fn uploaded_file(last_modified: SystemTime) -> UploadedFile {
+        UploadedFile {
+            uuid: "uuid".to_string(),
+            upload_key: "upload key".to_string(),
+            filen_parent: "parent".to_string(),
+            name: "a.txt".to_string(),
+            mime: "text/plain".to_string(),
+            key: [b'k'; 32],
+            size: 10,
+            chunks: 1,
+            last_modified,
+            hash: "hash".to_string(),
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_metadata_last_modified_in_millis() {
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let metadata = uploaded_file(modified).metadata();
+        assert_eq!(metadata.last_modified, Some(1_700_000_000_123));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_upload_chunks_from_reader() {
+        let sdk = FilenSDK::new();
+        let key = generate_rand_key().unwrap();
+
+        for (len, size_hint) in [(CHUNK_SIZE * 2 + 100, None), (CHUNK_SIZE * 2, Some(10)), (100, Some(100)), (0, None)] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 7 % 253) as u8).collect();
+            let mock = MockInteractionFunctions::default();
+
+            // Deliver the data in uneven pieces, like a socket would
+            let pieces = data.chunks(3001).map(|piece| Ok::<_, std::io::Error>(Bytes::copy_from_slice(piece)));
+            let mut reader = tokio_util::io::StreamReader::new(futures::stream::iter(pieces));
+
+            let target = ChunkUploadTarget {
+                uuid: "uuid",
+                upload_key: "upload key",
+                filen_parent: "parent",
+                key: &key,
+            };
+            let uploaded = sdk
+                .upload_chunks_from_reader(&mut reader, &target, size_hint, mock.clone())
+                .await
+                .unwrap();
+            let chunks = uploaded.chunks;
+            assert_eq!(uploaded.size, len as u64);
+            assert_eq!(chunks, len.div_ceil(CHUNK_SIZE) as u64);
+            assert_eq!(uploaded.hash, hex::encode(ring::digest::digest(&ring::digest::SHA512, &data)));
+            assert_eq!(uploaded.location.map(|location| location.region), (len > 0).then(|| "mock-region".to_string()));
+
+            let stored = mock.chunks.lock().unwrap().clone();
+            assert_eq!(stored.len() as u64, chunks);
+            let mut plaintext = Vec::new();
+            for i in 0..chunks {
+                let mut chunk = BytesMut::from(stored[&i].clone());
+                plaintext.extend_from_slice(&decrypt_v2_bytes(&mut chunk, &key).unwrap());
+            }
+            assert_eq!(plaintext, data);
+        }
     }
 }
\ No newline at end of file
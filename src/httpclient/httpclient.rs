@@ -7,7 +7,7 @@ use url::Url;
 use crate::{error::FilenSDKError, responses::fs::UploadChunkResponse};
 
 use super::{
-    endpoints::{string_url, Endpoints},
+    endpoints::{mirror_pool, string_url, Endpoints},
     FsURL,
 };
 
@@ -33,6 +33,33 @@ pub fn http_none() -> Option<HttpClientNone> {
     None
 }
 
+/// Turns responses that indicate a struggling mirror (server errors, rate limiting) into a
+/// `ReqwestError`, so the request fails over to another mirror.
+fn check_mirror_response(response: reqwest::Response) -> Result<reqwest::Response, FilenSDKError> {
+    let status = response.status();
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(FilenSDKError::ReqwestError {
+            err_str: format!("{} responded with {}", response.url(), status),
+        });
+    }
+
+    Ok(response)
+}
+
+/// Like `check_mirror_response`, but also refuses any other unsuccessful response, so error
+/// pages are never decrypted or cached as chunks.
+fn check_chunk_response(response: reqwest::Response) -> Result<reqwest::Response, FilenSDKError> {
+    let response = check_mirror_response(response)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(FilenSDKError::DownloadError {
+            err_str: format!("{} responded with {}", response.url(), status),
+        });
+    }
+
+    Ok(response)
+}
+
 /*
 This function assumes that a tokio runtime is already running.
 */
@@ -40,21 +67,14 @@ pub async fn download_into_memory(
     url: &FsURL,
     client: &reqwest::Client,
 ) -> Result<Bytes, FilenSDKError> {
-    let request = client.get(string_url(&url));
-
-    let response = request.send().await;
-    let response_text = match response {
-        Ok(response) => response.bytes().await,
-        Err(e) => {
-            return Err(FilenSDKError::ReqwestError {
-                err_str: e.to_string(),
-            })
-        }
-    };
-
-    Ok(response_text.map_err(|e| FilenSDKError::ReqwestError {
-        err_str: e.to_string(),
-    })?)
+    let (mirrors, index) = mirror_pool(url);
+
+    mirrors
+        .request(index, |mirror| async move {
+            let response = client.get(string_url(url, mirror)).send().await?;
+            Ok(check_chunk_response(response)?.bytes().await?)
+        })
+        .await
 }
 
 pub async fn download_to_file_streamed(
@@ -62,24 +82,21 @@ pub async fn download_to_file_streamed(
     client: &reqwest::Client,
     file_path: &str,
 ) -> Result<String, FilenSDKError> {
-    let request = client.get(string_url(&url));
+    let (mirrors, index) = mirror_pool(url);
 
-    let response = request.send().await;
-    let mut response = match response {
-        Ok(response) => response,
-        Err(e) => {
-            return Err(FilenSDKError::ReqwestError {
-                err_str: e.to_string(),
-            })
-        }
-    };
+    mirrors
+        .request(index, |mirror| async move {
+            let response = client.get(string_url(url, mirror)).send().await?;
+            let mut response = check_chunk_response(response)?;
 
-    let mut file = tokio::fs::File::create(file_path).await?;
-    while let Some(chunk) = response.chunk().await? {
-        file.write_all(&chunk).await?;
-    }
+            let mut file = tokio::fs::File::create(file_path).await?;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
 
-    Ok(file_path.to_string())
+            Ok(file_path.to_string())
+        })
+        .await
 }
 
 pub async fn upload_from_memory(
@@ -88,9 +105,14 @@ pub async fn upload_from_memory(
     data: Bytes,
     api_key: &str,
 ) -> Result<UploadChunkResponse, FilenSDKError> {
-    let request = client.post(string_url(&url)).body(data);
-    
-    make_upload_request(request, api_key).await
+    let (mirrors, index) = mirror_pool(&url);
+
+    mirrors
+        .request(index, |mirror| {
+            let request = client.post(string_url(&url, mirror)).body(data.clone());
+            make_upload_request(request, api_key)
+        })
+        .await
 }
 
 pub async fn upload_from_file(
@@ -99,16 +121,25 @@ pub async fn upload_from_file(
     file_path: &str,
     api_key: &str,
 ) -> Result<UploadChunkResponse, FilenSDKError> {
-    let file = tokio::fs::File::open(file_path).await.unwrap();
-
-    // Use streaming
-    let request = client
-        .post(string_url(&url))
-        .body(reqwest::Body::wrap_stream(
-            tokio_util::io::ReaderStream::new(file),
-        ));
-
-    make_upload_request(request, api_key).await
+    let (mirrors, index) = mirror_pool(&url);
+
+    mirrors
+        .request(index, |mirror| {
+            let url = string_url(&url, mirror);
+            async move {
+                let file = tokio::fs::File::open(file_path).await?;
+
+                // Use streaming
+                let request = client
+                    .post(url)
+                    .body(reqwest::Body::wrap_stream(
+                        tokio_util::io::ReaderStream::new(file),
+                    ));
+
+                make_upload_request(request, api_key).await
+            }
+        })
+        .await
 }
 
 async fn make_upload_request(
@@ -121,7 +152,7 @@ async fn make_upload_request(
 
     let response = request.send().await;
     let response = match response {
-        Ok(response) => response,
+        Ok(response) => check_mirror_response(response)?,
         Err(e) => {
             return Err(FilenSDKError::ReqwestError {
                 err_str: e.to_string(),
@@ -235,3 +266,35 @@ where
         });
     }
 }
+
+/// Same as `make_request`, but for endpoints that only report a status and return no data.
+pub async fn make_request_without_data<U>(
+    url: Endpoints,
+    client: Option<&reqwest::Client>,
+    parameters: Option<HashMap<&str, &str>>,
+    api_key: Option<&str>,
+    body: Option<U>,
+) -> Result<(), FilenSDKError>
+where
+    U: serde::Serialize,
+{
+    let response = construct_request(url, client, parameters, api_key, body)?
+        .send()
+        .await?;
+    let response_text = response.text().await?;
+
+    let response_json: FilenResponse<serde_json::Value> = serde_json::from_str(&response_text)
+        .map_err(|e| FilenSDKError::SerdeJsonError {
+            err_str: response_text.clone(),
+            err_msg: e.to_string(),
+        })?;
+
+    if response_json.status {
+        Ok(())
+    } else {
+        Err(FilenSDKError::APIError {
+            message: response_json.message,
+            code: response_json.code,
+        })
+    }
+}
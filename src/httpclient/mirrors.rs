@@ -0,0 +1,321 @@
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::FilenSDKError,
+    filensdk::{MIRROR_COOLDOWN, MIRROR_FAILOVER_ATTEMPTS, MIRROR_FAILURE_THRESHOLD},
+    FilenSDK,
+};
+
+use super::endpoints::{EGEST_MIRRORS, INGEST_MIRRORS};
+
+/// Weight of the newest sample in the latency and error rate averages
+const SMOOTHING: f64 = 0.25;
+/// A mirror this many times slower than the fastest one is skipped even for its own chunks,
+/// until it has been left alone for MIRROR_COOLDOWN and gets probed again
+const SLOW_MIRROR_FACTOR: f64 = 4.0;
+
+/// Health and usage of a single storage mirror, see `FilenSDK::mirror_stats`
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct FilenMirrorStats {
+    pub url: String,
+    pub requests: u64,
+    pub failures: u64,
+    /// Moving average of failed requests, between 0 and 1
+    pub error_rate: f64,
+    /// Moving average of successful request durations, if any request succeeded yet
+    pub latency_ms: Option<f64>,
+    /// Whether the mirror is currently skipped after repeated failures
+    pub circuit_open: bool,
+}
+
+#[derive(Default)]
+struct MirrorHealth {
+    requests: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    error_rate: f64,
+    latency: Option<f64>,
+    last_request: Option<Instant>,
+    /// Set while the circuit is open. Once the cooldown passes the mirror gets another chance,
+    /// and a single failure opens the circuit again until a request succeeds.
+    open_until: Option<Instant>,
+}
+
+impl MirrorHealth {
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|until| now < until)
+    }
+
+    /// Lower is better. Mirrors without a latency sample score as fast, so they get tried.
+    fn score(&self) -> f64 {
+        (self.latency.unwrap_or(0.0) + 0.1) * (1.0 + 10.0 * self.error_rate)
+    }
+
+    fn is_slower_than(&self, other: &MirrorHealth, now: Instant) -> bool {
+        let recently_used = self
+            .last_request
+            .is_some_and(|last| now.duration_since(last) < MIRROR_COOLDOWN);
+
+        match (self.latency, other.latency) {
+            (Some(latency), Some(other)) => recently_used && latency > SLOW_MIRROR_FACTOR * other,
+            _ => false,
+        }
+    }
+}
+
+/// Storage mirrors of one kind (egest or ingest) with their health.
+///
+/// Chunks are spread over the mirrors by index. A chunk's own mirror is skipped while its
+/// circuit is open or while it is far slower than the others, and requests that fail with a
+/// network error are retried on the healthiest remaining mirror.
+pub struct MirrorPool {
+    urls: &'static [&'static str],
+    health: Mutex<Vec<MirrorHealth>>,
+}
+
+impl MirrorPool {
+    pub fn new(urls: &'static [&'static str]) -> Self {
+        Self {
+            urls,
+            health: Mutex::new(urls.iter().map(|_| MirrorHealth::default()).collect()),
+        }
+    }
+
+    /// Sends a request for chunk `index` through `send`, which receives the mirror's base URL.
+    ///
+    /// `ReqwestError`s count as mirror failures and are retried on the next best mirror, up to
+    /// MIRROR_FAILOVER_ATTEMPTS mirrors. Other errors are returned as is.
+    pub async fn request<T, F, Fut>(&self, index: u64, mut send: F) -> Result<T, FilenSDKError>
+    where
+        F: FnMut(&'static str) -> Fut,
+        Fut: Future<Output = Result<T, FilenSDKError>>,
+    {
+        let preferred = index as usize % self.urls.len();
+        let mut tried = Vec::new();
+
+        loop {
+            let mirror = self.pick(preferred, &tried, Instant::now());
+            let start = Instant::now();
+            let result = send(self.urls[mirror]).await;
+
+            match &result {
+                Ok(_) => self.record_success(mirror, start.elapsed(), Instant::now()),
+                Err(FilenSDKError::ReqwestError { err_str }) => {
+                    self.record_failure(mirror, Instant::now());
+                    tried.push(mirror);
+
+                    if tried.len() < MIRROR_FAILOVER_ATTEMPTS.min(self.urls.len()) {
+                        eprintln!("Mirror {} failed: {}. Trying another mirror...", self.urls[mirror], err_str);
+                        continue;
+                    }
+                }
+                Err(_) => {}
+            }
+
+            return result;
+        }
+    }
+
+    pub fn stats(&self) -> Vec<FilenMirrorStats> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+
+        self.urls
+            .iter()
+            .zip(health.iter())
+            .map(|(url, health)| FilenMirrorStats {
+                url: url.to_string(),
+                requests: health.requests,
+                failures: health.failures,
+                error_rate: health.error_rate,
+                latency_ms: health.latency.map(|latency| latency * 1000.0),
+                circuit_open: health.is_open(now),
+            })
+            .collect()
+    }
+
+    fn pick(&self, preferred: usize, tried: &[usize], now: Instant) -> usize {
+        let health = self.health.lock().unwrap();
+        let untried = || (0..self.urls.len()).filter(|i| !tried.contains(i));
+
+        let best = untried()
+            .filter(|&i| !health[i].is_open(now))
+            .min_by(|&a, &b| health[a].score().total_cmp(&health[b].score()));
+
+        match best {
+            Some(best)
+                if tried.contains(&preferred)
+                    || health[preferred].is_open(now)
+                    || health[preferred].is_slower_than(&health[best], now) =>
+            {
+                best
+            }
+            Some(_) => preferred,
+            // Every remaining mirror is failing, try the one that recovers first
+            None => untried()
+                .min_by_key(|&i| health[i].open_until)
+                .unwrap_or(preferred),
+        }
+    }
+
+    fn record_success(&self, mirror: usize, latency: Duration, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[mirror];
+
+        let latency = latency.as_secs_f64();
+        health.requests += 1;
+        health.last_request = Some(now);
+        health.consecutive_failures = 0;
+        health.open_until = None;
+        health.error_rate -= SMOOTHING * health.error_rate;
+        health.latency = Some(health.latency.map_or(latency, |average| {
+            average + SMOOTHING * (latency - average)
+        }));
+    }
+
+    fn record_failure(&self, mirror: usize, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[mirror];
+
+        health.requests += 1;
+        health.last_request = Some(now);
+        health.failures += 1;
+        health.consecutive_failures += 1;
+        health.error_rate += SMOOTHING * (1.0 - health.error_rate);
+        if health.consecutive_failures >= MIRROR_FAILURE_THRESHOLD {
+            health.open_until = Some(now + MIRROR_COOLDOWN);
+        }
+    }
+}
+
+#[uniffi::export]
+impl FilenSDK {
+    /// Health of every download and upload mirror, shared by all SDK instances in the process.
+    pub fn mirror_stats(&self) -> Vec<FilenMirrorStats> {
+        EGEST_MIRRORS
+            .stats()
+            .into_iter()
+            .chain(INGEST_MIRRORS.stats())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URLS: [&str; 4] = ["https://a", "https://b", "https://c", "https://d"];
+
+    fn network_error() -> FilenSDKError {
+        FilenSDKError::ReqwestError {
+            err_str: "connection reset".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let pool = MirrorPool::new(&URLS);
+        let now = Instant::now();
+        assert_eq!(pool.pick(1, &[], now), 1);
+
+        for _ in 0..MIRROR_FAILURE_THRESHOLD {
+            pool.record_failure(1, now);
+        }
+        assert_ne!(pool.pick(1, &[], now), 1);
+        assert!(pool.stats()[1].circuit_open);
+
+        // After the cooldown the mirror gets another chance, a failure reopens the circuit
+        let later = now + MIRROR_COOLDOWN;
+        assert_eq!(pool.pick(1, &[], later), 1);
+        pool.record_failure(1, later);
+        assert_ne!(pool.pick(1, &[], later), 1);
+
+        pool.record_success(1, Duration::from_millis(50), later);
+        assert_eq!(pool.pick(1, &[], later), 1);
+
+        let stats = &pool.stats()[1];
+        assert_eq!((stats.requests, stats.failures), (MIRROR_FAILURE_THRESHOLD as u64 + 2, MIRROR_FAILURE_THRESHOLD as u64 + 1));
+        assert!(!stats.circuit_open);
+    }
+
+    #[test]
+    fn test_slow_mirror_is_avoided() {
+        let pool = MirrorPool::new(&URLS);
+        let now = Instant::now();
+        for i in 0..URLS.len() {
+            pool.record_success(i, Duration::from_millis(50), now);
+        }
+        pool.record_success(2, Duration::from_secs(10), now);
+
+        assert_eq!(pool.pick(0, &[], now), 0);
+        assert_ne!(pool.pick(2, &[], now), 2);
+
+        // Once it has been left alone for the cooldown, the slow mirror is probed again
+        assert_eq!(pool.pick(2, &[], now + MIRROR_COOLDOWN), 2);
+    }
+
+    #[test]
+    fn test_all_mirrors_failing() {
+        let pool = MirrorPool::new(&URLS);
+        let now = Instant::now();
+        for i in 0..URLS.len() {
+            for _ in 0..MIRROR_FAILURE_THRESHOLD {
+                pool.record_failure(i, now + Duration::from_secs(i as u64));
+            }
+        }
+
+        // The mirror whose circuit closes first is tried, skipping mirrors already tried
+        assert_eq!(pool.pick(3, &[], now), 0);
+        assert_eq!(pool.pick(3, &[0], now), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_fails_over() {
+        let pool = MirrorPool::new(&URLS);
+
+        let mut used = Vec::new();
+        let result = pool
+            .request(1, |mirror| {
+                used.push(mirror);
+                async move {
+                    match mirror {
+                        "https://b" => Err(network_error()),
+                        _ => Ok(mirror),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(used[0], "https://b");
+        assert_eq!(used.len(), 2);
+        assert_eq!(result, used[1]);
+        assert_eq!(pool.stats()[1].failures, 1);
+
+        // Errors that aren't caused by the mirror are returned without failover
+        let mut attempts = 0;
+        let result: Result<(), _> = pool
+            .request(0, |_| {
+                attempts += 1;
+                async { Err(FilenSDKError::InvalidPath { path: String::new() }) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        // Gives up after MIRROR_FAILOVER_ATTEMPTS mirrors
+        let mut attempts = 0;
+        let result: Result<(), _> = pool
+            .request(0, |_| {
+                attempts += 1;
+                async { Err(network_error()) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, MIRROR_FAILOVER_ATTEMPTS);
+    }
+}
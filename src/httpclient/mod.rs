@@ -2,10 +2,16 @@ mod endpoints;
 pub mod fs_download;
 pub mod fs_upload;
 pub mod httpclient;
+pub mod mirrors;
 
 pub use endpoints::Endpoints;
 pub use endpoints::FsURL;
-pub use httpclient::{download_into_memory, download_to_file_streamed, http_none, make_request};
+pub use fs_download::{ChunkSource, OrderlessDownloadOptions};
+pub use mirrors::FilenMirrorStats;
+pub use httpclient::{
+    download_into_memory, download_to_file_streamed, http_none, make_request,
+    make_request_without_data,
+};
 
 use crate::FilenSDK;
 
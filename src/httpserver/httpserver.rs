@@ -18,6 +18,8 @@ use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use crate::error::FilenSDKError;
+use crate::httpclient::ChunkSource;
+use crate::zip_export::ZipCompression;
 use crate::{filensdk, FilenSDK, CHUNK_SIZE};
 
 use super::config::{self, FilenHttpServerConfig};
@@ -96,6 +98,10 @@ async fn hello(
     filen_sdk: Arc<FilenSDK>,
     tmpdir: &str,
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    if req.uri().path() == "/zip" {
+        return Ok(zip_response(&req, filen_sdk).await);
+    }
+
     // Get query parameter "uuid"
     let uuid: Option<String> = match req.uri().query() {
         Some(query) => {
@@ -162,12 +168,9 @@ async fn hello(
     let tmpdir_clone = tmpdir.to_owned();
 
     let stream = filen_sdk.read_ahead_download_stream(
-        size,
+        ChunkSource::try_from(&file_info).unwrap(),
         start_byte.unwrap_or(0),
-        file_info.region,
-        file_info.bucket,
-        uuid,
-        String::from_utf8(file_info.key).unwrap(),
+        size,
     );
 
     let body_stream = StreamBody::new(convert_byte_stream_to_hyper_stream(stream));
@@ -192,4 +195,42 @@ async fn hello(
         )
         .body(boxed)
         .unwrap())
-}
\ No newline at end of file
+}
+
+/// `/zip?uuid=<uuid>&uuid=<uuid>&compression=deflate` streams a ZIP archive of the given files
+/// and folders, see `FilenSDK::export_zip`.
+async fn zip_response(
+    req: &Request<hyper::body::Incoming>,
+    filen_sdk: Arc<FilenSDK>,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut uuids = Vec::new();
+    let mut compression = ZipCompression::Store;
+    let query = req.uri().query().unwrap_or_default();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match (key.as_ref(), value.as_ref()) {
+            ("uuid", uuid) => uuids.push(uuid.to_string()),
+            ("compression", "deflate") => compression = ZipCompression::Deflate,
+            _ => {}
+        }
+    }
+
+    if uuids.is_empty() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(BoxBody::new(Full::new(Bytes::from("Missing query parameter 'uuid'"))))
+            .unwrap();
+    }
+
+    match filen_sdk.export_zip(uuids, compression).await {
+        Ok(stream) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/zip")
+            .header("Content-Disposition", "attachment; filename=\"download.zip\"")
+            .body(BoxBody::new(StreamBody::new(convert_byte_stream_to_hyper_stream(stream))))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(BoxBody::new(Full::new(Bytes::from(err.to_string()))))
+            .unwrap(),
+    }
+}
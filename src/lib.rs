@@ -3,14 +3,23 @@ uniffi::setup_scaffolding!();
 pub mod credentials;
 pub mod filensdk;
 pub mod download_stream;
+pub mod file_reader;
 pub mod auth;
 pub mod responses;
 pub mod error;
 pub mod dir;
 
 pub mod upload;
+pub mod resumable_upload;
 pub mod download;
+pub mod folder_download;
+pub mod folder_upload;
 pub mod file;
+pub mod path;
+pub mod tree;
+pub mod zip_export;
+pub mod chunk_cache;
+pub mod encrypted_archive;
 
 pub mod httpserver;
 // pub mod upload;
@@ -21,4 +30,5 @@ mod requests;
 mod mod_private;
 
 pub use filensdk::FilenSDK;
+pub use httpclient::FilenMirrorStats;
 pub use crypto::CHUNK_SIZE;
\ No newline at end of file
@@ -4,6 +4,7 @@ use bytes::{Bytes, BytesMut};
 
 use super::{net_interaction::FilenNetInteractionFunctions, DOWNLOAD_RETRIES};
 use crate::{error::FilenSDKError, httpclient::{download_into_memory, download_to_file_streamed, FsURL}, FilenSDK};
+use tokio::sync::Semaphore;
 
 impl FilenSDK {
     pub async fn attempt_download_chunk_task<T>(
@@ -47,6 +48,19 @@ impl FilenSDK {
     }
 
     pub async fn decrypt_chunk_task<T>(
+        link: &FsURL,
+        i: u64,
+        data: T,
+        key: &[u8],
+        download_funcs: &impl FilenNetInteractionFunctions<T>,
+    ) -> Result<BytesMut, FilenSDKError> {
+        Self::decrypt_chunk(link, i, data, key, download_funcs)
+    }
+
+    /// Blocking counterpart of `decrypt_chunk_task`, meant to be run on a blocking thread.
+    /// Chunks that fail to decrypt are discarded from `download_funcs`' cache.
+    pub fn decrypt_chunk<T>(
+        link: &FsURL,
         i: u64,
         data: T,
         key: &[u8],
@@ -55,14 +69,44 @@ impl FilenSDK {
         let mut data = download_funcs.decrypt_retrieve_data(data);
 
         if data.len() > 0 {
-            let decrypt_in_memory =
-                crate::crypto::file_decrypt::decrypt_v2_bytes(&mut data, key).unwrap();
-
-            return Ok(decrypt_in_memory);
+            crate::crypto::file_decrypt::decrypt_v2_bytes(&mut data, key).map_err(|e| {
+                download_funcs.discard_retrieved_data(link, i);
+                FilenSDKError::DownloadError {
+                    err_str: format!("Error decrypting chunk {}: {}", i, e),
+                }
+            })
         } else {
-            return Err(FilenSDKError::DownloadError {
+            Err(FilenSDKError::DownloadError {
                 err_str: format!("Error downloading chunk {}, empty message", i),
-            });
+            })
         }
     }
+
+    /// Downloads and decrypts chunk `i`. A download permit is held while downloading and a
+    /// decrypt permit while decrypting on a blocking thread.
+    pub async fn fetch_plaintext_chunk<T>(
+        link: FsURL,
+        i: u64,
+        key: String,
+        download_funcs: impl FilenNetInteractionFunctions<T>,
+        download_semaphore: Arc<Semaphore>,
+        decrypt_semaphore: Arc<Semaphore>,
+    ) -> Result<Bytes, FilenSDKError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let data = {
+            let _permit = download_semaphore.acquire_owned().await.unwrap();
+            Self::attempt_download_chunk_task(link.clone(), i, &download_funcs).await?
+        };
+
+        let permit = decrypt_semaphore.acquire_owned().await.unwrap();
+        let decrypted = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            Self::decrypt_chunk(&link, i, data, key.as_bytes(), &download_funcs)
+        })
+        .await??;
+
+        Ok(decrypted.freeze())
+    }
 }
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::error::FilenSDKError;
+
+pub const MANIFEST_EXTENSION: &str = "filenpart";
+
+/// Identifies the download a manifest belongs to. If any field differs from the current
+/// download (e.g. the remote file was replaced), the manifest is discarded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DownloadManifestHeader {
+    pub uuid: String,
+    pub size: u64,
+    /// `hash_fn` of the file key, the key changes whenever the file content does
+    pub key_hash: String,
+    pub start_chunk: u64,
+    pub end_chunk: u64,
+    /// Byte range of the file written to the output, which differs between exact and
+    /// chunk-aligned downloads of the same chunks
+    pub start_byte: u64,
+    pub end_byte: u64,
+}
+
+/// Sidecar file recording which chunks of a download have already been written to the output.
+///
+/// The first line is the JSON encoded header, every following line is the index of a completed
+/// chunk. Appending a line per chunk keeps progress tracking cheap even for files with many
+/// thousands of chunks, and a torn last line after a crash is simply ignored.
+pub struct DownloadManifest {
+    path: PathBuf,
+    file: tokio::fs::File,
+}
+
+/// `output` with `.extension` appended, e.g. `movie.mp4.filenpart`
+pub fn sidecar_path(output: &Path, extension: &str) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+impl DownloadManifest {
+    pub fn path_for(output: &Path) -> PathBuf {
+        sidecar_path(output, MANIFEST_EXTENSION)
+    }
+
+    /// Opens the manifest for `output` and returns the chunks that were completed by a previous
+    /// attempt. A missing, corrupt or mismatching manifest is replaced by a fresh one, in which
+    /// case no chunks are reported as completed.
+    pub async fn open(
+        output: &Path,
+        header: DownloadManifestHeader,
+    ) -> Result<(Self, HashSet<u64>), FilenSDKError> {
+        let path = Self::path_for(output);
+
+        if let Ok(contents) = tokio::fs::read_to_string(&path).await {
+            if let Some(completed) = Self::parse(&contents, &header) {
+                let file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .await?;
+
+                return Ok((Self { path, file }, completed));
+            }
+        }
+
+        Ok((Self::create(output, header).await?, HashSet::new()))
+    }
+
+    /// Creates a fresh manifest for `output`, replacing any existing one.
+    pub async fn create(output: &Path, header: DownloadManifestHeader) -> Result<Self, FilenSDKError> {
+        let path = Self::path_for(output);
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .await?;
+        file.write_all(format!("{}\n", serde_json::to_string(&header)?).as_bytes())
+            .await?;
+        file.flush().await?;
+
+        Ok(Self { path, file })
+    }
+
+    fn parse(contents: &str, expected: &DownloadManifestHeader) -> Option<HashSet<u64>> {
+        // Only newline terminated lines are complete, anything after the last newline is torn
+        let (contents, _torn) = contents.rsplit_once('\n')?;
+        let mut lines = contents.split('\n');
+
+        let header: DownloadManifestHeader = serde_json::from_str(lines.next()?).ok()?;
+        if &header != expected {
+            return None;
+        }
+
+        Some(
+            lines
+                .filter_map(|line| line.trim().parse::<u64>().ok())
+                .filter(|i| (header.start_chunk..header.end_chunk).contains(i))
+                .collect(),
+        )
+    }
+
+    /// Records `chunks` as written. Must only be called once the chunk data is synced to disk,
+    /// otherwise a crash can leave recorded chunks that never reached the output.
+    pub async fn mark_completed(&mut self, chunks: &[u64]) -> Result<(), FilenSDKError> {
+        let lines: String = chunks.iter().map(|i| format!("{}\n", i)).collect();
+        self.file.write_all(lines.as_bytes()).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+
+    /// Removes the manifest once the download has fully succeeded.
+    pub async fn finish(self) -> Result<(), FilenSDKError> {
+        drop(self.file);
+        tokio::fs::remove_file(&self.path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> DownloadManifestHeader {
+        DownloadManifestHeader {
+            uuid: "uuid".to_string(),
+            size: 10 * 1024 * 1024,
+            key_hash: "hash".to_string(),
+            start_chunk: 0,
+            end_chunk: 10,
+            start_byte: 0,
+            end_byte: 10 * 1024 * 1024,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manifest_resume() {
+        let output = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+
+        let (mut manifest, completed) = DownloadManifest::open(&output, header()).await.unwrap();
+        assert!(completed.is_empty());
+        manifest.mark_completed(&[3]).await.unwrap();
+        manifest.mark_completed(&[7]).await.unwrap();
+        drop(manifest);
+
+        // Same download resumes with the recorded chunks
+        let (manifest, completed) = DownloadManifest::open(&output, header()).await.unwrap();
+        assert_eq!(completed, HashSet::from([3, 7]));
+        drop(manifest);
+
+        // A changed remote file invalidates the manifest
+        let mut changed = header();
+        changed.key_hash = "other".to_string();
+        let (manifest, completed) = DownloadManifest::open(&output, changed).await.unwrap();
+        assert!(completed.is_empty());
+
+        manifest.finish().await.unwrap();
+        assert!(!DownloadManifest::path_for(&output).exists());
+    }
+
+    #[test]
+    fn test_manifest_parse() {
+        let header_line = serde_json::to_string(&header()).unwrap();
+
+        // "1" after the last newline may be a torn write of "12"
+        let torn = format!("{}\n4\n5\n1", header_line);
+        assert_eq!(
+            DownloadManifest::parse(&torn, &header()),
+            Some(HashSet::from([4, 5]))
+        );
+
+        // Out of range indices are ignored
+        let out_of_range = format!("{}\n4\n12\n", header_line);
+        assert_eq!(
+            DownloadManifest::parse(&out_of_range, &header()),
+            Some(HashSet::from([4]))
+        );
+
+        assert_eq!(DownloadManifest::parse("not json\n1\n", &header()), None);
+    }
+}
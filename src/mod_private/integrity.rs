@@ -0,0 +1,253 @@
+use std::{
+    io::Read,
+    path::Path,
+    sync::{Condvar, Mutex},
+};
+
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use ring::digest::{Context, SHA512};
+
+use crate::{error::FilenSDKError, file::FilenFileDetailed, CHUNK_SIZE};
+
+/// Hashes plaintext the way Filen clients do for the `hash` field of the file metadata
+pub struct PlaintextHasher {
+    context: Context,
+    len: u64,
+}
+
+impl PlaintextHasher {
+    pub fn new() -> Self {
+        Self {
+            context: Context::new(&SHA512),
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.context.update(data);
+        self.len += data.len() as u64;
+    }
+
+    /// Lowercase hex digest
+    pub fn finish(self) -> String {
+        hex::encode(self.context.finish())
+    }
+
+    /// Compares the hashed plaintext with the size and hash of `file`. Returns whether the hash
+    /// could be checked, files uploaded without a hash are only checked for their size.
+    pub fn verify(self, file: &FilenFileDetailed) -> Result<bool, FilenSDKError> {
+        self.verify_parts(&file.uuid, file.size, file.hash.as_deref())
+    }
+
+    /// `verify` for files known only by their uuid, size and hash
+    pub fn verify_parts(self, uuid: &str, size: u64, hash: Option<&str>) -> Result<bool, FilenSDKError> {
+        if self.len != size {
+            return Err(FilenSDKError::IntegrityError {
+                uuid: uuid.to_string(),
+                expected: format!("{} bytes", size),
+                actual: format!("{} bytes", self.len),
+            });
+        }
+
+        let Some(expected) = hash else {
+            return Ok(false);
+        };
+        let actual = self.finish();
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(FilenSDKError::IntegrityError {
+                uuid: uuid.to_string(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+
+        Ok(true)
+    }
+}
+
+/// Hashes chunks handed in by concurrent workers in chunk order. The worker holding chunk `i`
+/// waits until chunks `0..i` are hashed, so only reading and hashing are serialized while the
+/// encryption that follows runs in parallel.
+pub struct OrderedHasher {
+    state: Mutex<OrderedHasherState>,
+    advanced: Condvar,
+}
+
+struct OrderedHasherState {
+    next: u64,
+    hasher: PlaintextHasher,
+    failed: bool,
+}
+
+impl OrderedHasher {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(OrderedHasherState {
+                next: 0,
+                hasher: PlaintextHasher::new(),
+                failed: false,
+            }),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Hashes `data` as chunk `index` once every earlier chunk is hashed. Returns false instead
+    /// if `fail` was called, since the earlier chunks may never arrive.
+    pub fn update(&self, index: u64, data: &[u8]) -> bool {
+        let mut state = self
+            .advanced
+            .wait_while(self.state.lock().unwrap(), |state| state.next != index && !state.failed)
+            .unwrap();
+        if state.failed {
+            return false;
+        }
+
+        state.hasher.update(data);
+        state.next += 1;
+        self.advanced.notify_all();
+        true
+    }
+
+    /// Releases every waiting and future `update` call, for when a worker gives up
+    pub fn fail(&self) {
+        self.state.lock().unwrap().failed = true;
+        self.advanced.notify_all();
+    }
+
+    /// Lowercase hex digest of the chunks hashed so far
+    pub fn finish(&self) -> String {
+        self.take().finish()
+    }
+
+    /// Hasher of the chunks hashed so far, e.g. to `verify` them
+    pub fn take(&self) -> PlaintextHasher {
+        let mut state = self.state.lock().unwrap();
+        std::mem::replace(&mut state.hasher, PlaintextHasher::new())
+    }
+}
+
+/// Passes `stream` through, hashing it on the way. Once it ends, the plaintext is verified
+/// against `file` and a mismatch is yielded as a final `IntegrityError`.
+pub fn verified_stream(
+    file: FilenFileDetailed,
+    stream: impl Stream<Item = Result<Bytes, FilenSDKError>>,
+) -> impl Stream<Item = Result<Bytes, FilenSDKError>> {
+    async_stream::try_stream! {
+        let mut hasher = PlaintextHasher::new();
+        futures::pin_mut!(stream);
+
+        while let Some(data) = stream.try_next().await? {
+            hasher.update(&data);
+            yield data;
+        }
+
+        hasher.verify(&file)?;
+    }
+}
+
+/// Hashes the file at `path` in one sequential pass. This blocks, call it from a blocking thread.
+pub fn hash_file(path: &Path) -> std::io::Result<PlaintextHasher> {
+    let mut input = std::fs::File::open(path)?;
+    let mut hasher = PlaintextHasher::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        match input.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(hasher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::responses::auth::AuthVersion;
+
+    fn file(data: &[u8], hash: Option<String>) -> FilenFileDetailed {
+        FilenFileDetailed {
+            uuid: "uuid".to_string(),
+            region: String::new(),
+            bucket: String::new(),
+            name: String::new(),
+            size: data.len() as u64,
+            mime: String::new(),
+            key: Vec::new(),
+            last_modified: None,
+            hash,
+            parent: String::new(),
+            versioned: None,
+            trash: false,
+            version: AuthVersion::V2,
+        }
+    }
+
+    fn sha512(data: &[u8]) -> String {
+        hex::encode(ring::digest::digest(&SHA512, data))
+    }
+
+    #[tokio::test]
+    async fn test_verified_stream() {
+        let data = b"hello world".to_vec();
+        let chunks = || futures::stream::iter([Ok(Bytes::from_static(b"hello ")), Ok(Bytes::from_static(b"world"))]);
+
+        let expected = file(&data, Some(sha512(&data).to_uppercase()));
+        let output: Vec<Bytes> = verified_stream(expected, chunks()).try_collect().await.unwrap();
+        assert_eq!(output.concat(), data);
+
+        // Reordered chunks pass through, but fail the check at the end
+        let reordered = futures::stream::iter([Ok(Bytes::from_static(b"world")), Ok(Bytes::from_static(b"hello "))]);
+        let result: Result<Vec<Bytes>, _> =
+            verified_stream(file(&data, Some(sha512(&data))), reordered).try_collect().await;
+        assert!(matches!(result, Err(FilenSDKError::IntegrityError { .. })));
+
+        // Without a stored hash only the size is checked
+        let mut truncated = file(&data, None);
+        let result: Result<Vec<Bytes>, _> = verified_stream(truncated.clone(), chunks()).try_collect().await;
+        assert!(result.is_ok());
+        truncated.size += 1;
+        let result: Result<Vec<Bytes>, _> = verified_stream(truncated, chunks()).try_collect().await;
+        assert!(matches!(result, Err(FilenSDKError::IntegrityError { .. })));
+    }
+
+    #[test]
+    fn test_ordered_hasher() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 253) as u8).collect();
+        let hasher = std::sync::Arc::new(OrderedHasher::new());
+
+        // Chunks handed in from threads in reverse order are still hashed in order
+        let threads: Vec<_> = (0..10u64)
+            .rev()
+            .map(|i| {
+                let hasher = hasher.clone();
+                let chunk = data[i as usize * 1000..(i as usize + 1) * 1000].to_vec();
+                std::thread::spawn(move || hasher.update(i, &chunk))
+            })
+            .collect();
+        assert!(threads.into_iter().all(|thread| thread.join().unwrap()));
+        assert_eq!(hasher.finish(), sha512(&data));
+
+        // A failed pipeline releases workers waiting for a chunk that never comes
+        let hasher = std::sync::Arc::new(OrderedHasher::new());
+        let waiting = {
+            let hasher = hasher.clone();
+            std::thread::spawn(move || hasher.update(1, b"chunk"))
+        };
+        hasher.fail();
+        assert!(!waiting.join().unwrap());
+        assert!(!hasher.update(0, b"chunk"));
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let data: Vec<u8> = (0..CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &data).unwrap();
+
+        assert!(hash_file(&path).unwrap().verify(&file(&data, Some(sha512(&data)))).unwrap());
+        assert!(hash_file(&path).unwrap().verify(&file(&data, Some(sha512(b"other")))).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
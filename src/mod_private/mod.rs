@@ -1,5 +1,9 @@
 pub mod upload;
 pub mod download;
+pub mod download_manifest;
+pub mod integrity;
 pub mod net_interaction;
+pub mod partial_download;
+pub mod read_ahead;
 
 pub const DOWNLOAD_RETRIES: u64 = 3;
\ No newline at end of file
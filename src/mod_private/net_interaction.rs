@@ -2,10 +2,12 @@ use std::future::Future;
 
 use bytes::BytesMut;
 
-use crate::{error::FilenSDKError, httpclient::FsURL};
+use crate::{error::FilenSDKError, httpclient::FsURL, mod_private::integrity::OrderedHasher, responses::fs::UploadChunkResponse};
 
 mod low_disk;
 mod low_memory;
+#[cfg(test)]
+pub mod mock;
 
 pub use low_disk::LowDiskInteractionFunctions;
 pub use low_memory::LowMemoryInteractionFunctions;
@@ -16,7 +18,22 @@ pub trait FilenNetInteractionFunctions<T>: Send + Sync + Clone + 'static {
     /// data into memory for the decryption process. Different methods (streaming vs file) will use the 
     /// data in different ways.
     fn decrypt_retrieve_data(&self, data: T) -> BytesMut;
-    /// Encrypt and return the data, along with the encryption hash
-    fn encrypt_data(&self, input_file: &str, i: u64, key: &[u8; 32]) -> (T, String);
-    fn http_upload_data(&self, link: FsURL, data: T) -> impl Future<Output = Result<(), FilenSDKError>> + Send;
-}
\ No newline at end of file
+    /// Whether uploaded chunks are encrypted with counter based nonces
+    fn should_use_counter_nonce(&self) -> bool;
+    /// Called when the data retrieved for chunk `i` of `link` failed to decrypt, so that a
+    /// cached copy isn't served again and the next attempt downloads the chunk.
+    fn discard_retrieved_data(&self, _link: &FsURL, _i: u64) {}
+    /// Encrypt chunk `i` of `input` and return the data, along with the encryption hash. The
+    /// chunk is read without the file cursor, so workers can share `input`. The plaintext is fed
+    /// to `plaintext_hasher` if given.
+    fn encrypt_data(&self, input: &std::fs::File, i: u64, key: &[u8; 32], plaintext_hasher: Option<&OrderedHasher>) -> Result<(T, String), FilenSDKError>;
+    /// Uploads a chunk, returning where the ingest servers stored it
+    fn http_upload_data(&self, link: FsURL, data: T) -> impl Future<Output = Result<UploadChunkResponse, FilenSDKError>> + Send;
+}
+/// Uuid of the file a chunk download belongs to, which keys the chunk cache
+fn cached_file_uuid(link: &FsURL) -> Option<&str> {
+    match link {
+        FsURL::Egest(_, _, uuid, _) => Some(uuid),
+        FsURL::Igest(..) => None,
+    }
+}
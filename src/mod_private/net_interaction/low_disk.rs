@@ -2,41 +2,66 @@ use std::{future::Future, sync::Arc};
 
 use bytes::{Bytes, BytesMut};
 
-use crate::{error::FilenSDKError, httpclient::{download_into_memory, httpclient::upload_from_memory, FsURL}};
+use crate::{chunk_cache::EncryptedChunkCache, error::FilenSDKError, httpclient::{download_into_memory, httpclient::upload_from_memory, FsURL}, mod_private::integrity::OrderedHasher, responses::fs::UploadChunkResponse};
 
-use super::FilenNetInteractionFunctions;
+use super::{cached_file_uuid, FilenNetInteractionFunctions};
 
 
 #[derive(Clone)]
 pub struct LowDiskInteractionFunctions {
     pub client: Arc<reqwest::Client>,
     pub api_key: String,
-    pub should_use_counter_nonce: bool
+    pub should_use_counter_nonce: bool,
+    /// Consulted before downloading a chunk and filled afterwards, if set
+    pub chunk_cache: Option<Arc<EncryptedChunkCache>>,
 }
 
 
 impl FilenNetInteractionFunctions<Bytes> for LowDiskInteractionFunctions {
-    fn http_retrieve_data(&self, link: FsURL, _i: u64) -> impl Future<Output = Result<Bytes, FilenSDKError>> + Send {
-        async move { download_into_memory(&link, &self.client).await }
+    fn http_retrieve_data(&self, link: FsURL, i: u64) -> impl Future<Output = Result<Bytes, FilenSDKError>> + Send {
+        async move {
+            let cache = self.chunk_cache.as_ref().zip(cached_file_uuid(&link));
+            if let Some((cache, uuid)) = cache {
+                if let Some(data) = cache.get(uuid, i).await {
+                    return Ok(data);
+                }
+            }
+
+            let data = download_into_memory(&link, &self.client).await?;
+            if let Some((cache, uuid)) = cache {
+                cache.insert(uuid, i, &data).await;
+            }
+            Ok(data)
+        }
+    }
+
+    fn should_use_counter_nonce(&self) -> bool {
+        self.should_use_counter_nonce
+    }
+
+    fn discard_retrieved_data(&self, link: &FsURL, i: u64) {
+        if let Some((cache, uuid)) = self.chunk_cache.as_ref().zip(cached_file_uuid(link)) {
+            cache.evict(uuid, i);
+        }
     }
 
     fn decrypt_retrieve_data(&self, data: Bytes) -> BytesMut {
         data.into()
     }
 
-    fn encrypt_data(&self, input_file: &str, i: u64, key: &[u8; 32]) -> (Bytes, String) {
+    fn encrypt_data(&self, input: &std::fs::File, i: u64, key: &[u8; 32], plaintext_hasher: Option<&OrderedHasher>) -> Result<(Bytes, String), FilenSDKError> {
         let (encrypted_data, hash) = crate::crypto::file_encrypt::encrypt_v2_from_file(
-            &input_file,
+            input,
             None,
             key,
             i as usize,
-            self.should_use_counter_nonce
-        )
-        .unwrap();
-        (encrypted_data.unwrap(), hash)
+            self.should_use_counter_nonce,
+            plaintext_hasher,
+        )?;
+        Ok((encrypted_data.unwrap(), hash))
     }
 
-    fn http_upload_data(&self, link: FsURL, data: Bytes) -> impl Future<Output = Result<(), FilenSDKError>> + Send {
-        async move { upload_from_memory(link, &self.client, data, &self.api_key).await.map(|_| ()) }
+    fn http_upload_data(&self, link: FsURL, data: Bytes) -> impl Future<Output = Result<UploadChunkResponse, FilenSDKError>> + Send {
+        async move { upload_from_memory(link, &self.client, data, &self.api_key).await }
     }
 }
@@ -2,23 +2,48 @@ use std::{future::Future, sync::Arc};
 
 use bytes::{Bytes, BytesMut};
 
-use crate::{error::FilenSDKError, httpclient::{download_to_file_streamed, httpclient::upload_from_file, FsURL}};
+use crate::{chunk_cache::EncryptedChunkCache, error::FilenSDKError, httpclient::{download_to_file_streamed, httpclient::upload_from_file, FsURL}, mod_private::integrity::OrderedHasher, responses::fs::UploadChunkResponse};
 
-use super::FilenNetInteractionFunctions;
+use super::{cached_file_uuid, FilenNetInteractionFunctions};
 
 #[derive(Clone)]
 pub struct LowMemoryInteractionFunctions {
     pub client: Arc<reqwest::Client>,
     pub api_key: String,
     pub tmp_dir: String,
-    pub should_use_counter_nonce: bool
+    pub should_use_counter_nonce: bool,
+    /// Consulted before downloading a chunk and filled afterwards, if set
+    pub chunk_cache: Option<Arc<EncryptedChunkCache>>,
 }
 
 
 impl FilenNetInteractionFunctions<String> for LowMemoryInteractionFunctions {
     fn http_retrieve_data(&self, link: FsURL, i: u64) -> impl Future<Output = Result<String, FilenSDKError>> + Send {
         let tmp_dir = self.tmp_dir.clone() + "/" + &i.to_string();
-        async move { download_to_file_streamed(&link, &self.client, &tmp_dir).await }
+        async move {
+            let cache = self.chunk_cache.as_ref().zip(cached_file_uuid(&link));
+            if let Some((cache, uuid)) = cache {
+                if cache.copy_to(uuid, i, &tmp_dir).await {
+                    return Ok(tmp_dir);
+                }
+            }
+
+            let path = download_to_file_streamed(&link, &self.client, &tmp_dir).await?;
+            if let Some((cache, uuid)) = cache {
+                cache.insert_file(uuid, i, &path).await;
+            }
+            Ok(path)
+        }
+    }
+
+    fn should_use_counter_nonce(&self) -> bool {
+        self.should_use_counter_nonce
+    }
+
+    fn discard_retrieved_data(&self, link: &FsURL, i: u64) {
+        if let Some((cache, uuid)) = self.chunk_cache.as_ref().zip(cached_file_uuid(link)) {
+            cache.evict(uuid, i);
+        }
     }
 
     fn decrypt_retrieve_data(&self, data: String) -> BytesMut {
@@ -35,22 +60,22 @@ impl FilenNetInteractionFunctions<String> for LowMemoryInteractionFunctions {
         bytes.try_into_mut().unwrap()
     }
     
-    fn encrypt_data(&self, input_file: &str, i: u64, key: &[u8; 32]) -> (String, String) {
+    fn encrypt_data(&self, input: &std::fs::File, i: u64, key: &[u8; 32], plaintext_hasher: Option<&OrderedHasher>) -> Result<(String, String), FilenSDKError> {
         let output_file = self.tmp_dir.to_string() + "/" + &i.to_string();
         let (_encrypted_data, hash) = crate::crypto::file_encrypt::encrypt_v2_from_file(
-            &input_file,
+            input,
             Some(&output_file),
             key,
             i as usize,
-            self.should_use_counter_nonce
-        )
-        .unwrap();
-        (output_file, hash)
+            self.should_use_counter_nonce,
+            plaintext_hasher,
+        )?;
+        Ok((output_file, hash))
     }
     
-    fn http_upload_data(&self, link: FsURL, data: String) -> impl Future<Output = Result<(), FilenSDKError>> + Send {
+    fn http_upload_data(&self, link: FsURL, data: String) -> impl Future<Output = Result<UploadChunkResponse, FilenSDKError>> + Send {
         async move { 
-            let fut = upload_from_file(link, &self.client, &data, &self.api_key).await.map(|_| ());
+            let fut = upload_from_file(link, &self.client, &data, &self.api_key).await;
             std::fs::remove_file(&data).unwrap();
             fut
         }
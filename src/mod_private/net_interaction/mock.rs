@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    crypto::{file_encrypt::encrypt_v2_bytes, CHUNK_SIZE},
+    error::FilenSDKError,
+    httpclient::FsURL,
+    mod_private::integrity::OrderedHasher,
+    responses::fs::UploadChunkResponse,
+};
+
+use super::FilenNetInteractionFunctions;
+
+/// In-memory stand-in for the egest/ingest servers. Chunks are stored encrypted exactly as the
+/// real servers would store them, which allows exercising (and benchmarking) the transfer
+/// pipelines without network access.
+#[derive(Clone, Default)]
+pub struct MockInteractionFunctions {
+    pub chunks: Arc<Mutex<HashMap<u64, Bytes>>>,
+    /// Time every chunk spends being prepared for decryption, to make overlapping decrypt
+    /// workers observable
+    pub decrypt_delay: Option<Duration>,
+    active_decrypts: Arc<AtomicUsize>,
+    /// Most chunks that were prepared for decryption at the same time
+    pub max_decrypts: Arc<AtomicUsize>,
+}
+
+impl MockInteractionFunctions {
+    /// Encrypts `plaintext` chunk by chunk with `key` and stores it as if it had been uploaded.
+    pub fn from_plaintext(plaintext: &[u8], key: &[u8; 32]) -> Self {
+        let chunks = plaintext
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let (data, _hash) = encrypt_v2_bytes(chunk, key, i, false).unwrap();
+                (i as u64, data)
+            })
+            .collect();
+
+        Self {
+            chunks: Arc::new(Mutex::new(chunks)),
+            ..Default::default()
+        }
+    }
+}
+
+fn chunk_index(link: &FsURL) -> u64 {
+    match link {
+        FsURL::Egest(_, _, _, index) => *index,
+        FsURL::Igest(_, _, index, _, _) => *index,
+    }
+}
+
+impl FilenNetInteractionFunctions<Bytes> for MockInteractionFunctions {
+    fn http_retrieve_data(&self, link: FsURL, i: u64) -> impl Future<Output = Result<Bytes, FilenSDKError>> + Send {
+        let chunk = self.chunks.lock().unwrap().get(&chunk_index(&link)).cloned();
+        async move {
+            chunk.ok_or(FilenSDKError::DownloadError {
+                err_str: format!("Mock chunk {} does not exist", i),
+            })
+        }
+    }
+
+    fn decrypt_retrieve_data(&self, data: Bytes) -> BytesMut {
+        let active = self.active_decrypts.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_decrypts.fetch_max(active, Ordering::SeqCst);
+        if let Some(delay) = self.decrypt_delay {
+            std::thread::sleep(delay);
+        }
+        self.active_decrypts.fetch_sub(1, Ordering::SeqCst);
+
+        data.into()
+    }
+
+    fn should_use_counter_nonce(&self) -> bool {
+        false
+    }
+
+    fn encrypt_data(&self, input: &std::fs::File, i: u64, key: &[u8; 32], plaintext_hasher: Option<&OrderedHasher>) -> Result<(Bytes, String), FilenSDKError> {
+        let (encrypted_data, hash) =
+            crate::crypto::file_encrypt::encrypt_v2_from_file(input, None, key, i as usize, false, plaintext_hasher)?;
+        Ok((encrypted_data.unwrap(), hash))
+    }
+
+    fn http_upload_data(&self, link: FsURL, data: Bytes) -> impl Future<Output = Result<UploadChunkResponse, FilenSDKError>> + Send {
+        self.chunks.lock().unwrap().insert(chunk_index(&link), data);
+        async move {
+            Ok(UploadChunkResponse {
+                region: "mock-region".to_string(),
+                bucket: "mock-bucket".to_string(),
+            })
+        }
+    }
+}
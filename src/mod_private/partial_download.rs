@@ -0,0 +1,167 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use super::download_manifest::{sidecar_path, DownloadManifest, DownloadManifestHeader};
+use crate::{error::FilenSDKError, filensdk::DOWNLOAD_MANIFEST_SAVE_INTERVAL};
+
+pub const PARTIAL_EXTENSION: &str = "filendownload";
+
+/// Single file download target. Chunks are written into `<output>.filendownload` next to the
+/// final path, and the file is only renamed to `output` once every chunk has been written and
+/// synced, so other tools never see a half written file under the final name.
+///
+/// Progress is tracked with a `DownloadManifest`, which allows an interrupted download to pick up
+/// the partial file again. Written chunks are only recorded in it after the partial file has
+/// been synced, at most every DOWNLOAD_MANIFEST_SAVE_INTERVAL.
+pub struct PartialDownload {
+    output: PathBuf,
+    partial: PathBuf,
+    file: Arc<std::fs::File>,
+    manifest: DownloadManifest,
+    /// Chunks written but not yet synced and recorded in the manifest
+    unsynced: Vec<u64>,
+    last_save: Instant,
+    /// Chunks written by a previous attempt
+    pub completed: HashSet<u64>,
+}
+
+impl PartialDownload {
+    /// Opens (or resumes) the partial file for `output` and preallocates it to `len` bytes.
+    pub async fn open(
+        output: &Path,
+        header: DownloadManifestHeader,
+        len: u64,
+    ) -> Result<Self, FilenSDKError> {
+        let partial = sidecar_path(output, PARTIAL_EXTENSION);
+        let (mut manifest, mut completed) = DownloadManifest::open(output, header.clone()).await?;
+
+        // Recorded progress is meaningless without the data it describes
+        if !completed.is_empty() && !partial.exists() {
+            manifest = DownloadManifest::create(output, header).await?;
+            completed.clear();
+        }
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&partial)
+            .await?;
+
+        if completed.is_empty() {
+            file.set_len(0).await?;
+        }
+        file.set_len(len).await?;
+
+        Ok(Self {
+            output: output.to_path_buf(),
+            partial,
+            file: Arc::new(file.into_std().await),
+            manifest,
+            unsynced: Vec::new(),
+            last_save: Instant::now(),
+            completed,
+        })
+    }
+
+    /// Shared handle to the partial file, chunks can be written concurrently with
+    /// `write_output_at` and read back with `read_exact_at`.
+    pub fn file(&self) -> Arc<std::fs::File> {
+        self.file.clone()
+    }
+
+    /// Records chunk `i` as done. Must only be called after its data has been written.
+    pub async fn mark_completed(&mut self, i: u64) -> Result<(), FilenSDKError> {
+        self.unsynced.push(i);
+        if self.last_save.elapsed() >= DOWNLOAD_MANIFEST_SAVE_INTERVAL {
+            self.save_progress().await?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the partial file and records every chunk written so far in the manifest, e.g.
+    /// before giving up on a failed download.
+    pub async fn save_progress(&mut self) -> Result<(), FilenSDKError> {
+        if !self.unsynced.is_empty() {
+            let file = self.file.clone();
+            tokio::task::spawn_blocking(move || file.sync_data()).await??;
+            self.manifest.mark_completed(&self.unsynced).await?;
+            self.unsynced.clear();
+        }
+
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    /// Syncs the partial file to disk and atomically moves it to the final output path.
+    pub async fn finish(self) -> Result<(), FilenSDKError> {
+        let file = self.file;
+        tokio::task::spawn_blocking(move || file.sync_all()).await??;
+
+        tokio::fs::rename(&self.partial, &self.output).await?;
+        self.manifest.finish().await
+    }
+
+    /// Removes the partial file and its manifest without publishing anything, so the next
+    /// attempt starts over.
+    pub async fn discard(self) -> Result<(), FilenSDKError> {
+        drop(self.file);
+        tokio::fs::remove_file(&self.partial).await?;
+        self.manifest.finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::file_decrypt::write_output_at, CHUNK_SIZE};
+
+    #[tokio::test]
+    async fn test_partial_download_finish() {
+        let output = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        let header = DownloadManifestHeader {
+            uuid: "uuid".to_string(),
+            size: CHUNK_SIZE as u64 + 2,
+            key_hash: "hash".to_string(),
+            start_chunk: 0,
+            end_chunk: 2,
+            start_byte: 0,
+            end_byte: CHUNK_SIZE as u64 + 2,
+        };
+
+        let mut download = PartialDownload::open(&output, header.clone(), header.size)
+            .await
+            .unwrap();
+        write_output_at(&download.file(), b"zz", CHUNK_SIZE as u64).unwrap();
+        download.mark_completed(1).await.unwrap();
+        download.save_progress().await.unwrap();
+        // Written but never synced, so it must not count as completed after a crash
+        write_output_at(&download.file(), &vec![b'a'; CHUNK_SIZE], 0).unwrap();
+        download.mark_completed(0).await.unwrap();
+        drop(download);
+
+        // Nothing appears under the final name until the download is finished
+        assert!(!output.exists());
+
+        let mut download = PartialDownload::open(&output, header.clone(), header.size)
+            .await
+            .unwrap();
+        assert_eq!(download.completed, HashSet::from([1]));
+        write_output_at(&download.file(), &vec![b'a'; CHUNK_SIZE], 0).unwrap();
+        download.mark_completed(0).await.unwrap();
+        download.finish().await.unwrap();
+
+        let data = std::fs::read(&output).unwrap();
+        assert_eq!(data.len(), CHUNK_SIZE + 2);
+        assert_eq!(&data[CHUNK_SIZE..], b"zz");
+        assert!(!sidecar_path(&output, PARTIAL_EXTENSION).exists());
+        assert!(!DownloadManifest::path_for(&output).exists());
+
+        std::fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::task::{JoinError, JoinHandle};
+
+use crate::{
+    filensdk::{MAX_READ_AHEAD_THREADS, MIN_READ_AHEAD_CHUNKS},
+    CHUNK_SIZE,
+};
+
+/// Weight of the newest sample in the moving averages
+const SMOOTHING: f64 = 0.25;
+
+/// Number of chunks a streaming download keeps in flight ahead of its consumer.
+///
+/// The window starts at MIN_READ_AHEAD_CHUNKS. It tracks how long chunks take to download and
+/// how quickly the consumer asks for the next one, and aims for enough chunks in flight to cover
+/// one chunk latency of consumption. Growth doubles the window, shrinking happens one chunk at a
+/// time so short hiccups don't throw away work.
+pub struct ReadAheadWindow {
+    size: u64,
+    latency: Option<f64>,
+    interval: Option<f64>,
+}
+
+fn smooth(average: Option<f64>, sample: Duration) -> Option<f64> {
+    let sample = sample.as_secs_f64();
+    Some(average.map_or(sample, |average| average + SMOOTHING * (sample - average)))
+}
+
+impl ReadAheadWindow {
+    pub fn new() -> Self {
+        Self {
+            size: MIN_READ_AHEAD_CHUNKS,
+            latency: None,
+            interval: None,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Records a consumed chunk. `latency` is how long the chunk took to download and decrypt,
+    /// `interval` how long the consumer took before asking for it, not counting time spent
+    /// waiting for the data.
+    pub fn record(&mut self, latency: Duration, interval: Duration) {
+        self.latency = smooth(self.latency, latency);
+        self.interval = smooth(self.interval, interval);
+
+        let target = self.target();
+        if target > self.size {
+            self.size = std::cmp::min(self.size * 2, target);
+        } else if target < self.size {
+            self.size -= 1;
+        }
+    }
+
+    /// Halves the window, used when read-ahead buffers across the SDK exceed their memory budget
+    pub fn shrink(&mut self) {
+        self.size = std::cmp::max(self.size / 2, MIN_READ_AHEAD_CHUNKS);
+    }
+
+    fn target(&self) -> u64 {
+        let (Some(latency), Some(interval)) = (self.latency, self.interval) else {
+            return MIN_READ_AHEAD_CHUNKS;
+        };
+
+        // Chunks consumed while one chunk downloads, plus one to absorb jitter
+        let chunks = (latency / interval.max(0.001)).ceil() as u64 + 1;
+        chunks.clamp(MIN_READ_AHEAD_CHUNKS, MAX_READ_AHEAD_THREADS)
+    }
+}
+
+/// A spawned read-ahead chunk task. While queued it counts CHUNK_SIZE bytes against the shared
+/// read-ahead memory counter, and dropping it aborts the task, so discarding the queue (e.g. when
+/// the stream is dropped) frees both the memory and the connection.
+pub struct ReadAheadTask<T> {
+    handle: JoinHandle<(T, Duration)>,
+    reserved_bytes: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> ReadAheadTask<T> {
+    pub fn spawn(
+        future: impl Future<Output = T> + Send + 'static,
+        reserved_bytes: Arc<AtomicUsize>,
+    ) -> Self {
+        reserved_bytes.fetch_add(CHUNK_SIZE, Ordering::Relaxed);
+
+        Self {
+            handle: tokio::spawn(async move {
+                let start = Instant::now();
+                let output = future.await;
+                (output, start.elapsed())
+            }),
+            reserved_bytes,
+        }
+    }
+
+    /// Waits for the task, returning its output and how long it ran
+    pub async fn join(mut self) -> Result<(T, Duration), JoinError> {
+        (&mut self.handle).await
+    }
+}
+
+impl<T> Drop for ReadAheadTask<T> {
+    fn drop(&mut self) {
+        self.handle.abort();
+        self.reserved_bytes.fetch_sub(CHUNK_SIZE, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_grows_for_fast_consumer() {
+        let mut window = ReadAheadWindow::new();
+        let mut sizes = Vec::new();
+        for _ in 0..10 {
+            window.record(Duration::from_millis(200), Duration::from_millis(1));
+            sizes.push(window.size());
+        }
+
+        // Doubling, capped at the maximum window
+        assert_eq!(sizes[..3], [4, 8, 16]);
+        assert_eq!(window.size(), MAX_READ_AHEAD_THREADS);
+
+        window.shrink();
+        assert_eq!(window.size(), MAX_READ_AHEAD_THREADS / 2);
+    }
+
+    #[test]
+    fn test_window_stays_small_for_slow_consumer() {
+        let mut window = ReadAheadWindow::new();
+        for _ in 0..10 {
+            window.record(Duration::from_millis(100), Duration::from_secs(1));
+        }
+        assert_eq!(window.size(), MIN_READ_AHEAD_CHUNKS);
+
+        // Once the consumer slows down, the window drains one chunk at a time
+        let mut window = ReadAheadWindow::new();
+        for _ in 0..10 {
+            window.record(Duration::from_millis(200), Duration::from_millis(1));
+        }
+        let size = window.size();
+        window.record(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(window.size(), size - 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_task_releases_reservation() {
+        let reserved = Arc::new(AtomicUsize::new(0));
+
+        let finished = ReadAheadTask::spawn(async { 1 }, reserved.clone());
+        let pending = ReadAheadTask::spawn(std::future::pending::<u32>(), reserved.clone());
+        assert_eq!(reserved.load(Ordering::Relaxed), 2 * CHUNK_SIZE);
+
+        assert_eq!(finished.join().await.unwrap().0, 1);
+        drop(pending);
+        assert_eq!(reserved.load(Ordering::Relaxed), 0);
+    }
+}
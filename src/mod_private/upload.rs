@@ -1,11 +1,35 @@
+use std::future::Future;
+
 use crate::{
     error::FilenSDKError,
     httpclient::{make_request, Endpoints},
     requests::fs::MarkUploadAsDoneBody,
     responses::{auth::AuthVersion, fs::MarkUploadAsDone},
+    upload::UploadConflictPolicy,
     FilenSDK,
 };
 
+/// Highest ` (n)` suffix `UploadConflictPolicy::KeepBoth` tries before giving up
+const MAX_CONFLICT_SUFFIX: u64 = 1000;
+
+/// Outcome of checking the upload target for a file with the same name
+#[derive(Debug, PartialEq)]
+pub enum ConflictResolution {
+    /// Upload under `name`. With `UploadConflictPolicy::Overwrite` a file with that name may
+    /// exist, see the policy for what happens to it.
+    Upload { name: String },
+    /// Nothing should be uploaded, the existing file is kept
+    Skip(String),
+}
+
+/// Inserts ` (n)` in front of the extension, `report.pdf` becomes `report (1).pdf`.
+pub fn suffixed_name(name: &str, n: u64) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{} ({}){}", &name[..dot], n, &name[dot..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
 impl FilenSDK {
     pub async fn mark_upload_as_done(
         &self,
@@ -38,4 +62,136 @@ impl FilenSDK {
             }),
         ).await
     }
+
+    /// Checks `filen_parent` for a file named `name` and decides how the upload should proceed
+    /// according to `policy`. This runs before the first chunk is sent.
+    pub async fn resolve_upload_conflict(
+        &self,
+        filen_parent: &str,
+        name: &str,
+        policy: UploadConflictPolicy,
+    ) -> Result<ConflictResolution, FilenSDKError> {
+        resolve_conflict(name, policy, |candidate| {
+            self.file_exists(filen_parent.to_string(), candidate)
+        })
+        .await
+    }
+}
+
+/// `resolve_upload_conflict` with the existence check passed in as `file_exists`, which returns
+/// the uuid of the file with the given name if there is one.
+async fn resolve_conflict<F, Fut>(
+    name: &str,
+    policy: UploadConflictPolicy,
+    mut file_exists: F,
+) -> Result<ConflictResolution, FilenSDKError>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Option<String>, FilenSDKError>>,
+{
+    // Overwrite never looks at the folder, the upload behaves as if no policy was given
+    if policy == UploadConflictPolicy::Overwrite {
+        return Ok(ConflictResolution::Upload {
+            name: name.to_string(),
+        });
+    }
+
+    let Some(existing) = file_exists(name.to_string()).await? else {
+        return Ok(ConflictResolution::Upload {
+            name: name.to_string(),
+        });
+    };
+
+    match policy {
+        UploadConflictPolicy::Overwrite => Ok(ConflictResolution::Upload {
+            name: name.to_string(),
+        }),
+        UploadConflictPolicy::KeepBoth => {
+            for n in 1..=MAX_CONFLICT_SUFFIX {
+                let candidate = suffixed_name(name, n);
+                if file_exists(candidate.clone()).await?.is_none() {
+                    return Ok(ConflictResolution::Upload { name: candidate });
+                }
+            }
+            Err(FilenSDKError::FileAlreadyExists {
+                name: suffixed_name(name, MAX_CONFLICT_SUFFIX),
+                uuid: existing,
+            })
+        }
+        UploadConflictPolicy::Skip => Ok(ConflictResolution::Skip(existing)),
+        UploadConflictPolicy::Fail => Err(FilenSDKError::FileAlreadyExists {
+            name: name.to_string(),
+            uuid: existing,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suffixed_name() {
+        assert_eq!(suffixed_name("report.pdf", 1), "report (1).pdf");
+        assert_eq!(suffixed_name("archive.tar.gz", 2), "archive.tar (2).gz");
+        assert_eq!(suffixed_name("README", 3), "README (3)");
+        assert_eq!(suffixed_name(".bashrc", 1), ".bashrc (1)");
+    }
+
+    /// Resolves a conflict against a folder holding `existing`
+    async fn resolve(name: &str, policy: UploadConflictPolicy, existing: &[&str]) -> Result<ConflictResolution, FilenSDKError> {
+        resolve_conflict(name, policy, |candidate| {
+            let found = existing.iter().position(|name| name.eq_ignore_ascii_case(&candidate));
+            async move { Ok(found.map(|i| format!("uuid-{}", i))) }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_resolve_conflict() {
+        let upload = |name: &str| ConflictResolution::Upload { name: name.to_string() };
+
+        // Without a conflict every policy uploads under the requested name
+        for policy in [
+            UploadConflictPolicy::Overwrite,
+            UploadConflictPolicy::KeepBoth,
+            UploadConflictPolicy::Skip,
+            UploadConflictPolicy::Fail,
+        ] {
+            assert_eq!(resolve("a.txt", policy, &["b.txt"]).await.unwrap(), upload("a.txt"));
+        }
+
+        let existing = ["A.txt", "a (1).txt"];
+        assert_eq!(resolve("a.txt", UploadConflictPolicy::Overwrite, &existing).await.unwrap(), upload("a.txt"));
+        assert_eq!(resolve("a.txt", UploadConflictPolicy::KeepBoth, &existing).await.unwrap(), upload("a (2).txt"));
+        assert_eq!(
+            resolve("a.txt", UploadConflictPolicy::Skip, &existing).await.unwrap(),
+            ConflictResolution::Skip("uuid-0".to_string())
+        );
+        assert!(matches!(
+            resolve("a.txt", UploadConflictPolicy::Fail, &existing).await,
+            Err(FilenSDKError::FileAlreadyExists { uuid, .. }) if uuid == "uuid-0"
+        ));
+
+        // Overwrite uploads under the same name without checking the folder
+        let checks = std::cell::Cell::new(0);
+        let resolution = resolve_conflict("a.txt", UploadConflictPolicy::Overwrite, |_| {
+            checks.set(checks.get() + 1);
+            async { Ok(Some("uuid-0".to_string())) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(resolution, upload("a.txt"));
+        assert_eq!(checks.get(), 0);
+
+        // KeepBoth gives up once every suffix is taken
+        let taken: Vec<String> = std::iter::once("a.txt".to_string())
+            .chain((1..=MAX_CONFLICT_SUFFIX).map(|n| suffixed_name("a.txt", n)))
+            .collect();
+        let taken: Vec<&str> = taken.iter().map(String::as_str).collect();
+        assert!(matches!(
+            resolve("a.txt", UploadConflictPolicy::KeepBoth, &taken).await,
+            Err(FilenSDKError::FileAlreadyExists { .. })
+        ));
+    }
 }
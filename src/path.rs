@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{download::FilenFileDownloadResult, error::FilenSDKError, upload::FilenUploadResult, FilenSDK};
+
+/// A file or folder addressed by its absolute path from the user's base folder.
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct FilenPathEntry {
+    pub uuid: String,
+    pub parent: String,
+    pub name: String,
+    /// Normalized absolute path, e.g. `/a/b/c.txt`. The base folder is `/`.
+    pub path: String,
+    pub is_folder: bool,
+}
+
+/// In-memory cache of resolved path segments. Entries are keyed both by `(parent uuid, name)`
+/// for forward resolution and by uuid for reverse lookups.
+///
+/// Filen hashes names in lowercase, so lookups are case-insensitive by default. An exact match
+/// always wins over a case-insensitive one.
+#[derive(Default)]
+pub(crate) struct PathCache {
+    case_sensitive: bool,
+    children: HashMap<String, Vec<FilenPathEntry>>,
+    entries: HashMap<String, FilenPathEntry>,
+}
+
+impl PathCache {
+    pub(crate) fn insert(&mut self, entry: FilenPathEntry) {
+        if let Some(previous) = self.entries.remove(&entry.uuid) {
+            if let Some(siblings) = self.children.get_mut(&previous.parent) {
+                siblings.retain(|sibling| sibling.uuid != previous.uuid);
+            }
+        }
+
+        self.children
+            .entry(entry.parent.clone())
+            .or_default()
+            .push(entry.clone());
+        self.entries.insert(entry.uuid.clone(), entry);
+    }
+
+    pub(crate) fn lookup_child(&self, parent: &str, name: &str) -> Option<FilenPathEntry> {
+        let siblings = self.children.get(parent)?;
+
+        siblings
+            .iter()
+            .find(|entry| entry.name == name)
+            .or_else(|| {
+                if self.case_sensitive {
+                    None
+                } else {
+                    siblings
+                        .iter()
+                        .find(|entry| entry.name.to_lowercase() == name.to_lowercase())
+                }
+            })
+            .cloned()
+    }
+
+    pub(crate) fn lookup_uuid(&self, uuid: &str) -> Option<FilenPathEntry> {
+        self.entries.get(uuid).cloned()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.children.clear();
+        self.entries.clear();
+    }
+}
+
+/// Splits an absolute or relative remote path into its segments, resolving `.` and `..`.
+pub(crate) fn split_path(path: &str) -> Result<Vec<String>, FilenSDKError> {
+    let mut segments: Vec<String> = Vec::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(FilenSDKError::InvalidPath {
+                        path: path.to_string(),
+                    });
+                }
+            }
+            segment => segments.push(segment.to_string()),
+        }
+    }
+
+    Ok(segments)
+}
+
+pub(crate) fn join_path(parent: &str, name: &str) -> String {
+    if parent.ends_with('/') {
+        format!("{}{}", parent, name)
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+#[uniffi::export]
+impl FilenSDK {
+    /// Toggles whether path resolution compares names case-sensitively. Changing the setting
+    /// clears the path cache.
+    pub fn set_path_case_sensitive(&self, case_sensitive: bool) {
+        let mut cache = self.path_cache.lock().unwrap();
+        cache.case_sensitive = case_sensitive;
+        cache.clear();
+    }
+
+    /// Drops every cached path segment. Use this after files were moved or deleted by another
+    /// client.
+    pub fn clear_path_cache(&self) {
+        self.path_cache.lock().unwrap().clear();
+    }
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Resolves an absolute path such as `/a/b/c.txt` to the file or folder it points to. Every
+    /// listed folder is cached, so resolving siblings or deeper paths only lists new folders.
+    pub async fn resolve_path(&self, path: String) -> Result<FilenPathEntry, FilenSDKError> {
+        let segments = split_path(&path)?;
+        let mut current = self.root_path_entry()?;
+
+        for segment in segments {
+            if !current.is_folder {
+                return Err(FilenSDKError::InvalidPath { path });
+            }
+
+            let cached = self
+                .path_cache
+                .lock()
+                .unwrap()
+                .lookup_child(&current.uuid, &segment);
+
+            current = match cached {
+                Some(entry) => entry,
+                None => {
+                    self.cache_folder_children(&current).await?;
+                    self.path_cache
+                        .lock()
+                        .unwrap()
+                        .lookup_child(&current.uuid, &segment)
+                        .ok_or_else(|| FilenSDKError::FileDoesNotExist { file: path.clone() })?
+                }
+            };
+        }
+
+        Ok(current)
+    }
+
+    /// Returns the absolute path of a file or folder, walking up its parents until the base
+    /// folder is reached.
+    pub async fn path_of(&self, uuid: String) -> Result<String, FilenSDKError> {
+        let base_folder = self.base_folder()?;
+        if uuid == base_folder {
+            return Ok("/".to_string());
+        }
+
+        if let Some(entry) = self.path_cache.lock().unwrap().lookup_uuid(&uuid) {
+            return Ok(entry.path);
+        }
+
+        // The requested uuid may be a file, every ancestor is a folder
+        let (name, parent, is_folder) = match self.file_info(uuid.clone()).await {
+            Ok(info) => (info.name, info.parent, false),
+            Err(err) if err.is_not_found() => {
+                let info = self.dir_info(uuid.clone()).await?;
+                (info.name, info.parent, true)
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut names = vec![name.clone()];
+        let mut current = parent.clone();
+        let mut prefix = "/".to_string();
+        while current != base_folder {
+            if let Some(entry) = self.path_cache.lock().unwrap().lookup_uuid(&current) {
+                prefix = entry.path;
+                break;
+            }
+
+            let info = self.dir_info(current.clone()).await?;
+            names.push(info.name);
+            current = info.parent;
+        }
+
+        let path = names
+            .iter()
+            .rev()
+            .fold(prefix, |path, name| join_path(&path, name));
+
+        self.path_cache.lock().unwrap().insert(FilenPathEntry {
+            uuid,
+            parent,
+            name,
+            path: path.clone(),
+            is_folder,
+        });
+
+        Ok(path)
+    }
+
+    /// Resolves `path` to a folder, creating every missing folder along the way (like
+    /// `mkdir -p`).
+    pub async fn create_folder_path(&self, path: String) -> Result<FilenPathEntry, FilenSDKError> {
+        let segments = split_path(&path)?;
+        let mut current = self.root_path_entry()?;
+
+        for segment in segments {
+            if !current.is_folder {
+                return Err(FilenSDKError::InvalidPath { path });
+            }
+
+            let mut existing = self
+                .path_cache
+                .lock()
+                .unwrap()
+                .lookup_child(&current.uuid, &segment);
+
+            if existing.is_none() {
+                self.cache_folder_children(&current).await?;
+                existing = self
+                    .path_cache
+                    .lock()
+                    .unwrap()
+                    .lookup_child(&current.uuid, &segment);
+            }
+
+            current = match existing {
+                Some(entry) => entry,
+                None => {
+                    let uuid = self
+                        .create_folder(current.uuid.clone(), segment.clone())
+                        .await?;
+                    let entry = FilenPathEntry {
+                        uuid,
+                        parent: current.uuid.clone(),
+                        path: join_path(&current.path, &segment),
+                        name: segment,
+                        is_folder: true,
+                    };
+                    self.path_cache.lock().unwrap().insert(entry.clone());
+                    entry
+                }
+            };
+        }
+
+        if !current.is_folder {
+            return Err(FilenSDKError::InvalidPath { path });
+        }
+
+        Ok(current)
+    }
+
+    /// Convenience wrapper around `download_file` that addresses the file by path.
+    pub async fn download_path(
+        &self,
+        path: String,
+        output_file: String,
+    ) -> Result<FilenFileDownloadResult, FilenSDKError> {
+        let entry = self.resolve_path(path.clone()).await?;
+        if entry.is_folder {
+            return Err(FilenSDKError::PathIsDirectory { path });
+        }
+
+        self.download_file(entry.uuid, output_file).await
+    }
+
+    /// Uploads `input_file` to `remote_path`, creating any missing intermediate folders. The
+    /// last segment of `remote_path` is used as the file name.
+    pub async fn upload_to_path(
+        &self,
+        input_file: String,
+        remote_path: String,
+    ) -> Result<FilenUploadResult, FilenSDKError> {
+        let mut segments = split_path(&remote_path)?;
+        let name = segments.pop().ok_or(FilenSDKError::InvalidPath {
+            path: remote_path.clone(),
+        })?;
+        let parent = self.create_folder_path(segments.join("/")).await?;
+
+        let result = self
+            .upload_file(input_file, parent.uuid.clone(), name.clone())
+            .await?;
+
+        self.path_cache.lock().unwrap().insert(FilenPathEntry {
+            uuid: result.file.uuid.clone(),
+            parent: parent.uuid,
+            path: join_path(&parent.path, &name),
+            name,
+            is_folder: false,
+        });
+
+        Ok(result)
+    }
+}
+
+impl FilenSDK {
+    fn root_path_entry(&self) -> Result<FilenPathEntry, FilenSDKError> {
+        Ok(FilenPathEntry {
+            uuid: self.base_folder()?,
+            parent: String::new(),
+            name: String::new(),
+            path: "/".to_string(),
+            is_folder: true,
+        })
+    }
+
+    /// Lists `folder` and stores all of its children in the path cache.
+    async fn cache_folder_children(&self, folder: &FilenPathEntry) -> Result<(), FilenSDKError> {
+        let contents = self.dir_contents(folder.uuid.clone(), false).await?;
+
+        let mut cache = self.path_cache.lock().unwrap();
+        for child in contents {
+            cache.insert(FilenPathEntry {
+                uuid: child.uuid().to_string(),
+                parent: folder.uuid.clone(),
+                path: join_path(&folder.path, child.name()),
+                name: child.name().to_string(),
+                is_folder: child.is_dir(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uuid: &str, parent: &str, name: &str) -> FilenPathEntry {
+        FilenPathEntry {
+            uuid: uuid.to_string(),
+            parent: parent.to_string(),
+            name: name.to_string(),
+            path: join_path("/", name),
+            is_folder: false,
+        }
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("/a/b/c.txt").unwrap(), vec!["a", "b", "c.txt"]);
+        assert_eq!(split_path("a//b/./c/../d").unwrap(), vec!["a", "b", "d"]);
+        assert!(split_path("/").unwrap().is_empty());
+        assert!(split_path("/a/../..").is_err());
+    }
+
+    #[test]
+    fn test_cache_case_sensitivity() {
+        let mut cache = PathCache::default();
+        cache.insert(entry("1", "root", "Report.pdf"));
+        cache.insert(entry("2", "root", "report.pdf"));
+
+        // Exact matches win, otherwise fall back to a case-insensitive match
+        assert_eq!(cache.lookup_child("root", "report.pdf").unwrap().uuid, "2");
+        assert_eq!(cache.lookup_child("root", "Report.pdf").unwrap().uuid, "1");
+        assert!(cache.lookup_child("root", "REPORT.PDF").is_some());
+
+        cache.case_sensitive = true;
+        assert!(cache.lookup_child("root", "REPORT.PDF").is_none());
+    }
+
+    #[test]
+    fn test_cache_reinsert() {
+        let mut cache = PathCache::default();
+        cache.insert(entry("1", "root", "a"));
+        cache.insert(entry("1", "root", "b"));
+
+        assert!(cache.lookup_child("root", "a").is_none());
+        assert_eq!(cache.lookup_uuid("1").unwrap().name, "b");
+        assert_eq!(cache.lookup_child("root", "b").unwrap().uuid, "1");
+    }
+}
@@ -20,10 +20,49 @@ request_struct! {
         uuid: String,
     }
 
+    TrashBody {
+        uuid: String,
+    }
+
+    ExistsBody {
+        parent: String,
+        name_hashed: String,
+    }
+
     DirContentBody {
         uuid: String,
         folders_only: bool,
     }
+
+    DirInfoBody {
+        uuid: String,
+    }
+
+    DirSizeBody {
+        uuid: String,
+        sharer_id: i64,
+        receiver_id: i64,
+    }
+
+    DirTreeBody {
+        uuid: String,
+        device_id: String,
+        skip_cache: i64,
+        include_raw: i64,
+    }
+
+    DirCreateBody {
+        uuid: String,
+        name: String,
+        name_hashed: String,
+        parent: String,
+    }
+}
+
+/// Folder names are stored as encrypted JSON objects rather than raw strings.
+#[derive(Serialize, serde::Deserialize)]
+pub struct FolderMetadata {
+    pub name: String,
 }
 
 
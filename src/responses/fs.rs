@@ -31,6 +31,11 @@ response_struct! {
         version: AuthVersion,
     }
 
+    ExistsResponse {
+        exists: bool,
+        uuid: Option<String>,
+    }
+
     DirContentUpload {
         uuid: String,
         metadata: String,
@@ -60,6 +65,32 @@ response_struct! {
         uploads: Vec<DirContentUpload>,
         folders: Vec<DirContentFolder>,
     }
+
+    DirGetResponse {
+        uuid: String,
+        name_encrypted: String,
+        parent: String,
+    }
+
+    DirCreateResponse {
+        uuid: String,
+    }
+
+    DirSizeResponse {
+        size: u64,
+        folders: u64,
+        files: u64,
+    }
+}
+
+/// The tree endpoint returns every row as a compact JSON array instead of an object:
+///
+/// - files: `[uuid, bucket, region, chunks, parent, metadata, version, timestamp]`
+/// - folders: `[uuid, name, parent, ...]`
+#[derive(Deserialize, Debug)]
+pub struct DirTreeResponse {
+    pub files: Vec<Vec<serde_json::Value>>,
+    pub folders: Vec<Vec<serde_json::Value>>,
 }
 
 #[derive(uniffi::Enum)]
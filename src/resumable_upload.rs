@@ -0,0 +1,356 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::StreamExt;
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{
+    error::FilenSDKError,
+    filensdk::{MAX_UPLOAD_THREADS, UPLOAD_SESSION_SAVE_INTERVAL},
+    httpclient::FsURL,
+    mod_private::{
+        integrity::hash_file,
+        net_interaction::{FilenNetInteractionFunctions, LowDiskInteractionFunctions},
+        upload::ConflictResolution,
+    },
+    httpclient::fs_upload::UploadedFile,
+    responses::fs::UploadChunkResponse,
+    upload::{FilenUploadResult, UploadConflictPolicy},
+    FilenSDK, CHUNK_SIZE,
+};
+
+/// Size and modification time of an upload source, used to detect that it changed between an
+/// interrupted upload and its resumption
+#[derive(uniffi::Record, serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilenSourceFingerprint {
+    pub size: u64,
+    /// Milliseconds since the unix epoch
+    pub modified: u64,
+}
+
+impl FilenSourceFingerprint {
+    fn of(path: &str) -> Result<Self, FilenSDKError> {
+        let metadata = std::fs::metadata(path).map_err(|_| FilenSDKError::FileDoesNotExist {
+            file: path.to_string(),
+        })?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(Self {
+            size: metadata.len(),
+            modified: modified.as_millis() as u64,
+        })
+    }
+}
+
+/// Everything needed to finish an upload started by `upload_file_resumable`, persisted to its
+/// session file while the upload runs
+#[derive(uniffi::Record, serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct FilenUploadSession {
+    pub uuid: String,
+    pub upload_key: String,
+    /// Key the chunks and metadata are encrypted with
+    pub key: String,
+    pub input_file: String,
+    pub filen_parent: String,
+    /// Remote name after resolving conflicts
+    pub name: String,
+    pub mime: String,
+    pub should_use_counter_nonce: bool,
+    pub fingerprint: FilenSourceFingerprint,
+    pub chunks: u64,
+    /// Chunks confirmed by the server, in the order they finished
+    pub completed_chunks: Vec<u64>,
+    /// Where the ingest servers stored the chunks, empty until the first one is confirmed
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub bucket: String,
+}
+
+impl FilenUploadSession {
+    fn missing_chunks(&self) -> Vec<u64> {
+        let completed: HashSet<u64> = self.completed_chunks.iter().copied().collect();
+        (0..self.chunks).filter(|i| !completed.contains(i)).collect()
+    }
+
+    async fn load(path: &Path) -> Result<Self, FilenSDKError> {
+        Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?)
+    }
+
+    /// Written under a temporary name first, so a crash never leaves a truncated session
+    async fn save(&self, path: &Path) -> Result<(), FilenSDKError> {
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Uploads `input_file` like `upload_file_low_disk`, keeping a record of the upload in
+    /// `session_file`. If the upload fails, the record holds the chunks the server already has
+    /// and `resume_upload` sends only the rest. The session file is removed once the upload
+    /// has finished.
+    ///
+    /// Chunks may be sent across several runs, so the plaintext hash is computed in a separate
+    /// pass over the file that runs alongside the uploads.
+    ///
+    /// # Returns
+    ///
+    /// The details of the uploaded file, or of the existing file if it was skipped, see
+    /// `FilenUploadResult`
+    pub async fn upload_file_resumable(
+        &self,
+        input_file: String,
+        filen_parent: String,
+        name: String,
+        session_file: String,
+        should_use_counter_nonce: bool,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, FilenSDKError> {
+        let started = Instant::now();
+        let fingerprint = FilenSourceFingerprint::of(&input_file)?;
+
+        let file_name = match self
+            .resolve_upload_conflict(&filen_parent, &name, conflict_policy)
+            .await?
+        {
+            ConflictResolution::Upload { name } => name,
+            ConflictResolution::Skip(existing) => return self.skipped_upload(existing, started).await,
+        };
+
+        let session = FilenUploadSession {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            upload_key: String::from_utf8(crate::crypto::generate_rand_key()?.to_vec())?,
+            key: String::from_utf8(crate::crypto::generate_rand_key()?.to_vec())?,
+            mime: mime_guess::from_path(&input_file).first_or_octet_stream().to_string(),
+            input_file,
+            filen_parent,
+            name: file_name,
+            should_use_counter_nonce,
+            fingerprint,
+            chunks: fingerprint.size.div_ceil(CHUNK_SIZE as u64),
+            completed_chunks: Vec::new(),
+            region: String::new(),
+            bucket: String::new(),
+        };
+        // Saved before the first chunk is sent, so the record always covers what was uploaded
+        session.save(Path::new(&session_file)).await?;
+
+        self.complete_upload_session(session, Path::new(&session_file), started).await
+    }
+
+    /// Continues the upload recorded in `session_file` by `upload_file_resumable`, sending only
+    /// the chunks the server does not have yet.
+    ///
+    /// Fails with `UploadSourceChanged` if the source file was modified since the upload
+    /// started, in which case the upload has to be started over. The elapsed time in the result
+    /// only covers the resumed part.
+    pub async fn resume_upload(&self, session_file: String) -> Result<FilenUploadResult, FilenSDKError> {
+        let started = Instant::now();
+        let session = FilenUploadSession::load(Path::new(&session_file)).await?;
+
+        if FilenSourceFingerprint::of(&session.input_file)? != session.fingerprint {
+            return Err(FilenSDKError::UploadSourceChanged {
+                file: session.input_file,
+            });
+        }
+
+        self.complete_upload_session(session, Path::new(&session_file), started).await
+    }
+}
+
+impl FilenSDK {
+    async fn complete_upload_session(
+        &self,
+        mut session: FilenUploadSession,
+        session_file: &Path,
+        started: Instant,
+    ) -> Result<FilenUploadResult, FilenSDKError> {
+        let input_file = session.input_file.clone();
+        let hashing = tokio::task::spawn_blocking(move || hash_file(Path::new(&input_file)));
+
+        let upload_funcs = LowDiskInteractionFunctions {
+            client: self.client.clone(),
+            api_key: self.api_key()?,
+            should_use_counter_nonce: session.should_use_counter_nonce,
+            chunk_cache: None,
+        };
+        self.upload_missing_chunks(&mut session, session_file, upload_funcs).await?;
+        let hash = hashing.await??.finish();
+
+        let key = session_key(&session)?;
+        let location = (!session.region.is_empty()).then(|| UploadChunkResponse {
+            region: session.region.clone(),
+            bucket: session.bucket.clone(),
+        });
+        let result = self
+            .finish_upload(
+                UploadedFile {
+                    uuid: session.uuid,
+                    upload_key: session.upload_key,
+                    filen_parent: session.filen_parent,
+                    name: session.name,
+                    mime: session.mime,
+                    key,
+                    size: session.fingerprint.size,
+                    chunks: session.chunks,
+                    last_modified: SystemTime::UNIX_EPOCH + Duration::from_millis(session.fingerprint.modified),
+                    hash,
+                    location,
+                },
+                started,
+            )
+            .await?;
+
+        tokio::fs::remove_file(session_file).await?;
+        Ok(result)
+    }
+
+    /// Uploads the chunks of `session` that are not completed yet. Progress is saved to
+    /// `session_file` at most every UPLOAD_SESSION_SAVE_INTERVAL and once more when the
+    /// uploads stop, whether they succeeded or not.
+    async fn upload_missing_chunks<T>(
+        &self,
+        session: &mut FilenUploadSession,
+        session_file: &Path,
+        upload_funcs: impl FilenNetInteractionFunctions<T>,
+    ) -> Result<(), FilenSDKError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let key = session_key(session)?;
+        let (uuid, upload_key, filen_parent) = (
+            session.uuid.clone(),
+            session.upload_key.clone(),
+            session.filen_parent.clone(),
+        );
+        // Opened once and shared, chunks are read with positional reads
+        let input = Arc::new(std::fs::File::open(&session.input_file)?);
+
+        let mut uploads = futures::stream::iter(session.missing_chunks())
+            .map(|i| {
+                let upload_funcs = upload_funcs.clone();
+                let (uuid, upload_key, filen_parent) = (uuid.clone(), upload_key.clone(), filen_parent.clone());
+                let input = input.clone();
+                async move {
+                    let encrypt_funcs = upload_funcs.clone();
+                    let (data, hash) =
+                        tokio::task::spawn_blocking(move || encrypt_funcs.encrypt_data(&input, i, &key, None)).await??;
+
+                    let url = FsURL::Igest(uuid, upload_key, i, filen_parent, hash);
+                    let _permit = self.upload_semaphore.acquire().await.unwrap();
+                    let response = upload_funcs.http_upload_data(url, data).await?;
+                    Ok::<_, FilenSDKError>((i, response))
+                }
+            })
+            .buffer_unordered(MAX_UPLOAD_THREADS);
+
+        let mut last_save = Instant::now();
+        let result = loop {
+            match uploads.next().await {
+                Some(Ok((i, response))) => {
+                    session.completed_chunks.push(i);
+                    if session.region.is_empty() {
+                        session.region = response.region;
+                        session.bucket = response.bucket;
+                    }
+                    if last_save.elapsed() >= UPLOAD_SESSION_SAVE_INTERVAL {
+                        session.save(session_file).await?;
+                        last_save = Instant::now();
+                    }
+                }
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            }
+        };
+        drop(uploads);
+
+        session.save(session_file).await?;
+        result
+    }
+}
+
+fn session_key(session: &FilenUploadSession) -> Result<[u8; 32], FilenSDKError> {
+    session
+        .key
+        .as_bytes()
+        .try_into()
+        .map_err(|_| FilenSDKError::EncryptionError {
+            err_str: "Invalid file key in upload session".to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::{crypto::file_decrypt::decrypt_v2_bytes, mod_private::net_interaction::mock::MockInteractionFunctions};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_upload_missing_chunks() {
+        let sdk = FilenSDK::new();
+        let dir = std::env::temp_dir().join(format!("session-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let input_file = dir.join("input.bin");
+        let data: Vec<u8> = (0..CHUNK_SIZE * 4 + 10).map(|i| (i * 11 % 249) as u8).collect();
+        std::fs::write(&input_file, &data).unwrap();
+        let input_file = input_file.to_string_lossy().to_string();
+        let fingerprint = FilenSourceFingerprint::of(&input_file).unwrap();
+
+        let mut session = FilenUploadSession {
+            uuid: "uuid".to_string(),
+            upload_key: "upload key".to_string(),
+            key: String::from_utf8(crate::crypto::generate_rand_key().unwrap().to_vec()).unwrap(),
+            input_file,
+            filen_parent: "parent".to_string(),
+            name: "input.bin".to_string(),
+            mime: "application/octet-stream".to_string(),
+            should_use_counter_nonce: false,
+            fingerprint,
+            chunks: 5,
+            completed_chunks: vec![3, 0],
+            region: String::new(),
+            bucket: String::new(),
+        };
+        assert_eq!(session.missing_chunks(), [1, 2, 4]);
+
+        let session_file = dir.join("session.json");
+        let mock = MockInteractionFunctions::default();
+        sdk.upload_missing_chunks(&mut session, &session_file, mock.clone()).await.unwrap();
+
+        // Only the missing chunks were sent, and the saved session has all of them
+        let uploaded = mock.chunks.lock().unwrap().clone();
+        let mut indices: Vec<u64> = uploaded.keys().copied().collect();
+        indices.sort();
+        assert_eq!(indices, [1, 2, 4]);
+        let saved = FilenUploadSession::load(&session_file).await.unwrap();
+        assert_eq!(saved, session);
+        assert!(saved.missing_chunks().is_empty());
+        assert_eq!(saved.region, "mock-region");
+
+        let key = session_key(&session).unwrap();
+        for i in indices {
+            let mut chunk = BytesMut::from(uploaded[&i].clone());
+            let start = i as usize * CHUNK_SIZE;
+            let end = std::cmp::min(start + CHUNK_SIZE, data.len());
+            assert_eq!(&decrypt_v2_bytes(&mut chunk, &key).unwrap()[..], &data[start..end]);
+        }
+
+        // Any change to the source shows in its fingerprint
+        std::fs::write(&session.input_file, &data[1..]).unwrap();
+        assert_ne!(FilenSourceFingerprint::of(&session.input_file).unwrap(), fingerprint);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::Arc,
+};
+
+use futures::{lock::Mutex, stream::FuturesUnordered, StreamExt, TryStreamExt};
+use futures_core::Stream;
+use serde_json::Value;
+
+use crate::{
+    dir::{decrypt_file_metadata, decrypt_folder_name, fetch_dir_contents, DecryptedStreamedDirContentResponse, FilenFolderDetailed},
+    error::FilenSDKError,
+    file::FilenFileDetailed,
+    filensdk::MAX_DIR_LIST_THREADS,
+    httpclient::{make_request, Endpoints},
+    path::join_path,
+    requests::fs::DirTreeBody,
+    responses::{auth::AuthVersion, fs::DirTreeResponse},
+    FilenSDK,
+};
+
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenWalkOptions {
+    /// Deepest level to descend to, direct children of the root are at depth 1. `None` walks
+    /// the whole tree.
+    pub max_depth: Option<u32>,
+    pub include_files: bool,
+    pub include_folders: bool,
+    /// Number of folders listed concurrently, defaults to MAX_DIR_LIST_THREADS
+    pub concurrency: Option<u32>,
+    /// Fetch the whole tree with the gateway's single-call tree endpoint instead of listing
+    /// folder by folder. Faster for large trees, but the whole tree is buffered in memory.
+    pub use_tree_endpoint: bool,
+}
+
+impl Default for FilenWalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            include_files: true,
+            include_folders: true,
+            concurrency: None,
+            use_tree_endpoint: false,
+        }
+    }
+}
+
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenTreeEntry {
+    /// Path relative to the walked root, e.g. `a/b/c.txt`
+    pub relative_path: String,
+    pub depth: u32,
+    pub item: DecryptedStreamedDirContentResponse,
+}
+
+impl FilenTreeEntry {
+    fn is_included(&self, options: &FilenWalkOptions) -> bool {
+        if self.item.is_dir() {
+            options.include_folders
+        } else {
+            options.include_files
+        }
+    }
+}
+
+type TreeEntryStream = Pin<Box<dyn Stream<Item = Result<FilenTreeEntry, FilenSDKError>> + Send>>;
+
+/// Iterator wrapper around `walk_tree` for foreign code, see `DirContentsIterator`.
+#[derive(uniffi::Object)]
+pub struct FilenTreeWalker {
+    internal_stream: Arc<Mutex<TreeEntryStream>>,
+    filen_sdk: Arc<FilenSDK>,
+}
+
+#[uniffi::export]
+impl FilenTreeWalker {
+    #[uniffi::constructor]
+    pub fn new(filen_sdk: Arc<FilenSDK>, root_uuid: String, options: FilenWalkOptions) -> Self {
+        Self {
+            internal_stream: Arc::new(Mutex::new(Box::pin(
+                filen_sdk.walk_tree(root_uuid, options),
+            ))),
+            filen_sdk,
+        }
+    }
+
+    #[uniffi::method(name = "next")]
+    pub fn next_blocking(&self) -> Result<Option<FilenTreeEntry>, FilenSDKError> {
+        self.filen_sdk
+            .tokio_runtime
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .block_on(self.next())
+    }
+}
+
+impl FilenTreeWalker {
+    pub async fn next(&self) -> Result<Option<FilenTreeEntry>, FilenSDKError> {
+        self.internal_stream.lock().await.try_next().await
+    }
+}
+
+/// A folder waiting to be listed during a breadth-first walk
+struct PendingFolder {
+    uuid: String,
+    relative_path: String,
+    depth: u32,
+}
+
+impl FilenSDK {
+    /// Recursively walks the folder `root_uuid` breadth-first, listing up to
+    /// `options.concurrency` folders at once. Entries are yielded as soon as their parent folder
+    /// has been listed, so order between siblings of different folders is not guaranteed.
+    pub fn walk_tree(
+        &self,
+        root_uuid: String,
+        options: FilenWalkOptions,
+    ) -> impl Stream<Item = Result<FilenTreeEntry, FilenSDKError>> {
+        let client = self.client.clone();
+        let credentials = self
+            .api_key()
+            .and_then(|api_key| Ok((api_key, self.master_key()?)));
+
+        async_stream::try_stream! {
+            let (api_key, master_key) = credentials?;
+
+            if options.use_tree_endpoint {
+                let entries = fetch_tree(&client, &api_key, &master_key, &root_uuid).await?;
+                for entry in entries {
+                    let within_depth = options.max_depth.is_none_or(|max| entry.depth <= max);
+                    if within_depth && entry.is_included(&options) {
+                        yield entry;
+                    }
+                }
+            } else {
+                let concurrency = options
+                    .concurrency
+                    .map_or(MAX_DIR_LIST_THREADS, |c| c.max(1) as usize);
+                let folders_only = !options.include_files;
+
+                let mut pending = VecDeque::from([PendingFolder {
+                    uuid: root_uuid,
+                    relative_path: String::new(),
+                    depth: 0,
+                }]);
+                let mut in_flight = FuturesUnordered::new();
+
+                loop {
+                    while in_flight.len() < concurrency {
+                        let Some(folder) = pending.pop_front() else {
+                            break;
+                        };
+
+                        let client = client.clone();
+                        let api_key = api_key.clone();
+                        let master_key = master_key.clone();
+                        in_flight.push(async move {
+                            let contents = fetch_dir_contents(
+                                &client,
+                                &api_key,
+                                &master_key,
+                                folder.uuid.clone(),
+                                folders_only,
+                            )
+                            .await;
+                            (folder, contents)
+                        });
+                    }
+
+                    let Some((folder, contents)) = in_flight.next().await else {
+                        break;
+                    };
+
+                    for item in contents? {
+                        let entry = FilenTreeEntry {
+                            relative_path: child_path(&folder.relative_path, item.name()),
+                            depth: folder.depth + 1,
+                            item,
+                        };
+
+                        let can_descend = options.max_depth.is_none_or(|max| entry.depth < max);
+                        if entry.item.is_dir() && can_descend {
+                            pending.push_back(PendingFolder {
+                                uuid: entry.item.uuid().to_string(),
+                                relative_path: entry.relative_path.clone(),
+                                depth: entry.depth,
+                            });
+                        }
+
+                        if entry.is_included(&options) {
+                            yield entry;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        join_path(parent, name)
+    }
+}
+
+fn malformed_row(row: &[Value]) -> FilenSDKError {
+    FilenSDKError::SerdeJsonError {
+        err_msg: "Malformed dir tree row".to_string(),
+        err_str: format!("{:?}", row),
+    }
+}
+
+fn row_str(row: &[Value], index: usize) -> Result<String, FilenSDKError> {
+    row.get(index)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| malformed_row(row))
+}
+
+/// Fetches the whole tree below `root_uuid` in one request and orders it breadth-first.
+async fn fetch_tree(
+    client: &reqwest::Client,
+    api_key: &str,
+    master_key: &str,
+    root_uuid: &str,
+) -> Result<Vec<FilenTreeEntry>, FilenSDKError> {
+    let response: DirTreeResponse = make_request(
+        Endpoints::DirTree,
+        Some(client),
+        None,
+        Some(api_key),
+        Some(DirTreeBody {
+            uuid: root_uuid.to_string(),
+            device_id: uuid::Uuid::new_v4().to_string(),
+            skip_cache: 1,
+            include_raw: 0,
+        }),
+    )
+    .await?;
+
+    let mut items = Vec::with_capacity(response.files.len() + response.folders.len());
+    for row in &response.folders {
+        let uuid = row_str(row, 0)?;
+        if uuid == root_uuid {
+            continue;
+        }
+
+        items.push(DecryptedStreamedDirContentResponse::Folders(FilenFolderDetailed {
+            uuid,
+            name: decrypt_folder_name(&row_str(row, 1)?, master_key)?,
+            parent: row_str(row, 2)?,
+            color: None,
+            timestamp: row.get(3).and_then(Value::as_u64).unwrap_or(0),
+            favorited: 0,
+            is_sync: None,
+            is_default: None,
+        }));
+    }
+
+    for row in &response.files {
+        let metadata = decrypt_file_metadata(row_str(row, 5)?, master_key)?;
+        let version: AuthVersion = row
+            .get(6)
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .ok_or_else(|| malformed_row(row))?;
+
+        items.push(DecryptedStreamedDirContentResponse::Uploads(FilenFileDetailed {
+            uuid: row_str(row, 0)?,
+            bucket: row_str(row, 1)?,
+            region: row_str(row, 2)?,
+            parent: row_str(row, 4)?,
+            mime: metadata.mime.unwrap_or_default(),
+            name: metadata.name,
+            size: metadata.size.unwrap_or(0),
+            key: metadata.key,
+            last_modified: metadata.last_modified,
+            hash: metadata.hash,
+            versioned: None,
+            trash: false,
+            version,
+        }));
+    }
+
+    Ok(order_tree(root_uuid, items))
+}
+
+/// Computes relative paths and depths for a flat list of tree items and orders them
+/// breadth-first. Items whose ancestry does not lead back to `root_uuid` are dropped.
+fn order_tree(root_uuid: &str, items: Vec<DecryptedStreamedDirContentResponse>) -> Vec<FilenTreeEntry> {
+    let folders: HashMap<String, (String, String)> = items
+        .iter()
+        .filter_map(|item| match item {
+            DecryptedStreamedDirContentResponse::Folders(folder) => Some((
+                folder.uuid.clone(),
+                (folder.name.clone(), folder.parent.clone()),
+            )),
+            DecryptedStreamedDirContentResponse::Uploads(_) => None,
+        })
+        .collect();
+
+    let folder_location = |uuid: &str| -> Option<(String, u32)> {
+        let mut names = Vec::new();
+        let mut current = uuid.to_string();
+
+        while current != root_uuid {
+            // A cycle or a detached subtree would never reach the root
+            if names.len() > folders.len() {
+                return None;
+            }
+
+            let (name, parent) = folders.get(&current)?;
+            names.push(name.as_str());
+            current = parent.clone();
+        }
+
+        names.reverse();
+        Some((names.join("/"), names.len() as u32))
+    };
+
+    let mut entries: Vec<FilenTreeEntry> = items
+        .into_iter()
+        .filter_map(|item| {
+            let parent = match &item {
+                DecryptedStreamedDirContentResponse::Uploads(file) => file.parent.clone(),
+                DecryptedStreamedDirContentResponse::Folders(folder) => folder.parent.clone(),
+            };
+            let (parent_path, parent_depth) = folder_location(&parent)?;
+
+            Some(FilenTreeEntry {
+                relative_path: child_path(&parent_path, item.name()),
+                depth: parent_depth + 1,
+                item,
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| {
+        a.depth
+            .cmp(&b.depth)
+            .then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(uuid: &str, name: &str, parent: &str) -> DecryptedStreamedDirContentResponse {
+        DecryptedStreamedDirContentResponse::Folders(FilenFolderDetailed {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            parent: parent.to_string(),
+            color: None,
+            timestamp: 0,
+            favorited: 0,
+            is_sync: None,
+            is_default: None,
+        })
+    }
+
+    #[test]
+    fn test_order_tree() {
+        let items = vec![
+            folder("c", "c", "b"),
+            folder("b", "b", "a"),
+            folder("a", "a", "root"),
+            folder("x", "x", "detached"),
+            folder("d", "d", "root"),
+        ];
+
+        let entries = order_tree("root", items);
+        let paths: Vec<(&str, u32)> = entries
+            .iter()
+            .map(|e| (e.relative_path.as_str(), e.depth))
+            .collect();
+
+        assert_eq!(paths, vec![("a", 1), ("d", 1), ("a/b", 2), ("a/b/c", 3)]);
+    }
+
+    #[test]
+    fn test_order_tree_cycle() {
+        let items = vec![folder("a", "a", "b"), folder("b", "b", "a")];
+        assert!(order_tree("root", items).is_empty());
+    }
+}
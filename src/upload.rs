@@ -4,20 +4,68 @@
 
 // create another thread for uploading which is limited by the upload semaphore and MAX UPLOAD THREADS
 
-use std::io::Read;
+use std::{io::Read, sync::Arc};
 
 use bytes::Bytes;
+use futures::TryStreamExt;
+use tokio::io::AsyncRead;
 use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
 
-use crate::{httpclient::{self, httpclient::{upload_from_file, upload_from_memory}}, mod_private::net_interaction::{LowDiskInteractionFunctions, LowMemoryInteractionFunctions}, FilenSDK};
+use crate::{error::FilenSDKError, file::FilenFileDetailed, httpclient::{self, fs_upload::{ChunkUploadTarget, UploadedFile}, httpclient::{upload_from_file, upload_from_memory}}, mod_private::{net_interaction::{LowDiskInteractionFunctions, LowMemoryInteractionFunctions}, upload::ConflictResolution}, FilenSDK, CHUNK_SIZE};
 
+/// What to do when a file with the same name already exists in the target folder. Names are
+/// compared case-insensitively, the same way Filen hashes them.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UploadConflictPolicy {
+    /// Upload the file under the same name without checking the folder first, which is what
+    /// `upload_file`, `upload_file_low_disk` and `upload_file_low_memory` do. The SDK neither
+    /// reads, moves nor deletes the existing file. What happens to it is up to the server, which
+    /// currently keeps it as a previous version of the new file, like it does for the official
+    /// clients. This is not guaranteed by the SDK.
+    Overwrite,
+    /// Upload the file under a free name by appending ` (1)`, ` (2)`, ... to the file stem. The
+    /// default for the functions taking a policy, so existing files are never touched.
+    #[default]
+    KeepBoth,
+    /// Do not upload anything and return the details of the existing file, with `skipped` set.
+    Skip,
+    /// Return `FilenSDKError::FileAlreadyExists` without uploading anything.
+    Fail,
+}
+
+/// Outcome of an upload
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct FilenUploadResult {
+    /// The uploaded file, or the existing file if the upload was skipped. Built from what the
+    /// upload already knows, so it matches `file_info` without another request.
+    pub file: FilenFileDetailed,
+    pub chunks: u64,
+    /// Time from the start of the upload until it was marked as done
+    pub elapsed_ms: u64,
+    /// SHA-512 hex digest of the uploaded plaintext, as stored in the file metadata. For a
+    /// skipped upload, the hash of the existing file if it has one.
+    pub hash: Option<String>,
+    /// Whether the upload was skipped because of `UploadConflictPolicy::Skip`
+    pub skipped: bool,
+}
+
+/// Supplies the content of an upload, see `upload_from_reader`. Implemented by foreign code,
+/// e.g. to upload from a platform stream.
+#[uniffi::export(with_foreign)]
+pub trait FilenUploadReader: Send + Sync {
+    /// Returns up to `max_len` bytes, or `None` once the content has ended.
+    fn read(&self, max_len: u64) -> Result<Option<Vec<u8>>, FilenSDKError>;
+}
 
 #[uniffi_async_export]
 impl FilenSDK {
     /// Uploads a file to the filen service, automatically handling encryption and threading the upload
     /// process. Optimized for scenarios where memory is not a concern. Rather than writing uploaded chunks
-    /// to a separate file, the chunks are stored in memory and encrypted in memory. At a maximum, MAX_UPLOAD_THREADS
-    /// * 2 * CHUNK_SIZE amount of memory will be used (more depending on how malloc functions on the system).
+    /// to a separate file, the chunks are stored in memory and encrypted in memory. Up to MAX_ENCRYPT_THREADS
+    /// workers each hold a chunk and its ciphertext, on top of up to MAX_UPLOAD_THREADS encrypted chunks
+    /// waiting in the upload channel and MAX_UPLOAD_THREADS being uploaded. At a maximum,
+    /// (2 * MAX_ENCRYPT_THREADS + 2 * MAX_UPLOAD_THREADS) * CHUNK_SIZE amount of memory will be used (more
+    /// depending on how malloc functions on the system).
     /// 
     /// # Arguments
     /// 
@@ -28,7 +76,7 @@ impl FilenSDK {
     /// 
     /// # Returns
     /// 
-    /// The uuid of the uploaded file
+    /// The details of the uploaded file
     /// 
     /// # Extra Info
     /// 
@@ -37,13 +85,38 @@ impl FilenSDK {
     /// plaintext. generate_rand_inv has an extremely low chance of generating the same nonce twice, but it is still
     /// possible. The counter nonce is a safer option since it is guaranteed to be unique for every chunk. The only
     /// concern is that all files with have the same nonce for the same chunk index.
+    /// 
+    /// Uploads under `name` even if a file with that name exists, see `UploadConflictPolicy::Overwrite`.
     pub async fn upload_file_low_disk(
         &self,
         input_file: String,
         filen_parent: String,
         name: String,
         should_use_counter_nonce: bool,
-    ) -> Result<String, crate::error::FilenSDKError> {
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
+        self.upload_file_low_disk_with_policy(
+            input_file,
+            filen_parent,
+            name,
+            should_use_counter_nonce,
+            UploadConflictPolicy::Overwrite,
+        )
+        .await
+    }
+
+    /// Same as `upload_file_low_disk`, but with an explicit conflict policy.
+    /// 
+    /// # Returns
+    /// 
+    /// The details of the uploaded file, or of the existing file if it was skipped
+    pub async fn upload_file_low_disk_with_policy(
+        &self,
+        input_file: String,
+        filen_parent: String,
+        name: String,
+        should_use_counter_nonce: bool,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
         let client = self.client.clone();
         let api_key = self.api_key()?;
         let input_file = input_file.to_string();
@@ -52,10 +125,12 @@ impl FilenSDK {
             &input_file.clone(),
             &filen_parent,
             &name,
+            conflict_policy,
             LowDiskInteractionFunctions {
                 client: client.clone(),
                 api_key: api_key.clone(),
                 should_use_counter_nonce,
+                chunk_cache: None,
             },
         )
         .await
@@ -64,10 +139,11 @@ impl FilenSDK {
 
     /// Uploads a file to the filen service, automatically handling encryption and threading the upload
     /// process. Optimized for scenarios where memory is a concern. Rather than storing chunks to memory,
-    /// the chunks are written to disk and encrypted in memory. Only one encrypt thread is ran at a time,
-    /// but multiple upload threads can be ran at once. However, upload threads use streamed uploads, so
-    /// the memory usage is minimal. At a maximum, CHUNK_SIZE + MAX_UPLOAD_THREADS * STREAM_MEMORY amount
-    /// of memory will be used. **NOTE: This function causes disk usage to double due to the temporary files
+    /// the chunks are written to disk and encrypted in memory. Up to MAX_ENCRYPT_THREADS workers each hold
+    /// a chunk while encrypting it, on top of up to MAX_UPLOAD_THREADS encrypted chunks waiting on disk in
+    /// the upload channel. Upload threads use streamed uploads, so the memory usage of the uploads is
+    /// minimal. At a maximum, 2 * MAX_ENCRYPT_THREADS * CHUNK_SIZE + MAX_UPLOAD_THREADS * STREAM_MEMORY
+    /// amount of memory will be used. **NOTE: This function causes disk usage to double due to the temporary files
     /// created for encryption.**
     /// 
     /// # Arguments
@@ -80,7 +156,7 @@ impl FilenSDK {
     /// 
     /// # Returns
     /// 
-    /// The uuid of the uploaded file
+    /// The details of the uploaded file
     /// 
     /// # Extra Info
     /// 
@@ -89,6 +165,8 @@ impl FilenSDK {
     /// plaintext. generate_rand_inv has an extremely low chance of generating the same nonce twice, but it is still
     /// possible. The counter nonce is a safer option since it is guaranteed to be unique for every chunk. The only
     /// concern is that all files with have the same nonce for the same chunk index.
+    /// 
+    /// Uploads under `name` even if a file with that name exists, see `UploadConflictPolicy::Overwrite`.
     pub async fn upload_file_low_memory(
         &self,
         input_file: String,
@@ -96,7 +174,32 @@ impl FilenSDK {
         name: String,
         tmp_output_dir: String,
         should_use_counter_nonce: bool,
-    ) -> Result<String, crate::error::FilenSDKError> {
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
+        self.upload_file_low_memory_with_policy(
+            input_file,
+            filen_parent,
+            name,
+            tmp_output_dir,
+            should_use_counter_nonce,
+            UploadConflictPolicy::Overwrite,
+        )
+        .await
+    }
+
+    /// Same as `upload_file_low_memory`, but with an explicit conflict policy.
+    /// 
+    /// # Returns
+    /// 
+    /// The details of the uploaded file, or of the existing file if it was skipped
+    pub async fn upload_file_low_memory_with_policy(
+        &self,
+        input_file: String,
+        filen_parent: String,
+        name: String,
+        tmp_output_dir: String,
+        should_use_counter_nonce: bool,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
         let client = self.client.clone();
         let api_key = self.api_key()?;
         let input_file = input_file.to_string();
@@ -110,11 +213,13 @@ impl FilenSDK {
             &input_file.clone(),
             &filen_parent,
             &name,
+            conflict_policy,
             LowMemoryInteractionFunctions {
                 client: client.clone(),
                 api_key: api_key.clone(),
                 tmp_dir: tmp_output_dir.clone(),
                 should_use_counter_nonce,
+                chunk_cache: None,
             },
         )
         .await
@@ -124,26 +229,26 @@ impl FilenSDK {
     Convience functions
     */
 
-    /// Calls `upload_file_low_disk` with `should_use_counter_nonce` set to false which aligns with
-    /// the default behavior of the filen service's own SDK.
+    /// Calls `upload_file_low_disk` with `should_use_counter_nonce` set to false which aligns with the default
+    /// behavior of the filen service's own SDK.
     pub async fn upload_file_low_disk_default(
         &self,
         input_file: String,
         filen_parent: String,
         name: String,
-    ) -> Result<String, crate::error::FilenSDKError> {
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
         self.upload_file_low_disk(input_file, filen_parent, name, false).await
     }
 
-    /// Calls `upload_file_low_memory` with `should_use_counter_nonce` set to false which aligns with
-    /// the default behavior of the filen service's own SDK.
+    /// Calls `upload_file_low_memory` with `should_use_counter_nonce` set to false which aligns with the default
+    /// behavior of the filen service's own SDK.
     pub async fn upload_file_low_memory_default(
         &self,
         input_file: String,
         filen_parent: String,
         name: String,
         tmp_output_dir: String,
-    ) -> Result<String, crate::error::FilenSDKError> {
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
         self.upload_file_low_memory(input_file, filen_parent, name, tmp_output_dir, false).await
     }
 
@@ -156,7 +261,130 @@ impl FilenSDK {
         input_file: String,
         filen_parent: String,
         name: String,
-    ) -> Result<String, crate::error::FilenSDKError> {
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
         self.upload_file_low_disk(input_file, filen_parent, name, false).await
     }
-}
\ No newline at end of file
+
+    /// Same as `upload_file`, but with an explicit conflict policy.
+    pub async fn upload_file_with_policy(
+        &self,
+        input_file: String,
+        filen_parent: String,
+        name: String,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
+        self.upload_file_low_disk_with_policy(input_file, filen_parent, name, false, conflict_policy).await
+    }
+
+    /// Uploads `data` as a file named `name` in `filen_parent`, without writing it to disk first.
+    /// An existing file with the same name is handled according to `conflict_policy`.
+    pub async fn upload_bytes(
+        &self,
+        data: Vec<u8>,
+        filen_parent: String,
+        name: String,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
+        let size_hint = Some(data.len() as u64);
+        self.upload_stream(&data[..], filen_parent, name, size_hint, conflict_policy).await
+    }
+
+    /// Uploads everything `reader` returns until it ends, see `upload_stream`. `reader` is
+    /// called from a blocking thread.
+    pub async fn upload_from_reader(
+        &self,
+        reader: Arc<dyn FilenUploadReader>,
+        filen_parent: String,
+        name: String,
+        size_hint: Option<u64>,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError> {
+        let pieces = futures::stream::try_unfold(reader, |reader| async move {
+            let (reader, data) = tokio::task::spawn_blocking(move || {
+                let data = reader.read(CHUNK_SIZE as u64);
+                (reader, data)
+            })
+            .await?;
+
+            Ok::<_, FilenSDKError>(data?.map(|data| (Bytes::from(data), reader)))
+        });
+
+        let reader = tokio_util::io::StreamReader::new(Box::pin(pieces.map_err(std::io::Error::other)));
+        self.upload_stream(reader, filen_parent, name, size_hint, conflict_policy).await
+    }
+}
+
+impl FilenSDK {
+    /// Uploads everything `reader` returns until it ends as a file named `name` in
+    /// `filen_parent`. The length does not need to be known up front, chunks are encrypted and
+    /// uploaded as they are read and the file is finalized with the size actually read.
+    /// `size_hint` avoids allocating a whole chunk for small uploads.
+    ///
+    /// An existing file with the same name is handled according to `conflict_policy`, before
+    /// anything is read. The mime type is guessed from `name` and the modification time is the
+    /// time of the upload.
+    pub async fn upload_stream<R>(
+        &self,
+        mut reader: R,
+        filen_parent: String,
+        name: String,
+        size_hint: Option<u64>,
+        conflict_policy: UploadConflictPolicy,
+    ) -> Result<FilenUploadResult, crate::error::FilenSDKError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let started = std::time::Instant::now();
+        let upload_funcs = LowDiskInteractionFunctions {
+            client: self.client.clone(),
+            api_key: self.api_key()?,
+            should_use_counter_nonce: false,
+            chunk_cache: None,
+        };
+
+        let file_name = match self
+            .resolve_upload_conflict(&filen_parent, &name, conflict_policy)
+            .await?
+        {
+            ConflictResolution::Upload { name } => name,
+            ConflictResolution::Skip(existing) => return self.skipped_upload(existing, started).await,
+        };
+
+        let key = crate::crypto::generate_rand_key()?;
+        let uuid = uuid::Uuid::new_v4().to_string();
+        let upload_key = String::from_utf8(crate::crypto::generate_rand_key()?.to_vec()).unwrap();
+        let mime = mime_guess::from_path(&file_name).first_or_octet_stream().to_string();
+
+        let uploaded = self
+            .upload_chunks_from_reader(
+                &mut reader,
+                &ChunkUploadTarget {
+                    uuid: &uuid,
+                    upload_key: &upload_key,
+                    filen_parent: &filen_parent,
+                    key: &key,
+                },
+                size_hint,
+                upload_funcs,
+            )
+            .await?;
+
+        self.finish_upload(
+            UploadedFile {
+                uuid,
+                upload_key,
+                filen_parent,
+                name: file_name,
+                mime,
+                key,
+                size: uploaded.size,
+                chunks: uploaded.chunks,
+                last_modified: std::time::SystemTime::now(),
+                hash: uploaded.hash,
+                location: uploaded.location,
+            },
+            started,
+        )
+        .await
+    }
+}
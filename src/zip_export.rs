@@ -0,0 +1,592 @@
+use std::{collections::HashSet, io::Write, pin::Pin};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::write::DeflateEncoder;
+use futures::{StreamExt, TryStreamExt};
+use futures_core::Stream;
+use tokio::io::AsyncWriteExt;
+use uniffi_shared_tokio_runtime_proc::uniffi_async_export;
+
+use crate::{
+    dir::DecryptedStreamedDirContentResponse,
+    error::FilenSDKError,
+    file::FilenFileDetailed,
+    httpclient::ChunkSource,
+    mod_private::{integrity::verified_stream, upload::suffixed_name},
+    tree::{FilenTreeEntry, FilenWalkOptions},
+    FilenSDK,
+};
+
+const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x07064b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const METHOD_STORE: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// Files at least this large are written with ZIP64 sizes. Kept well below 4 GiB so that
+/// deflate output, which can be slightly larger than its input, always fits 32 bit fields
+/// otherwise.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZipCompression {
+    /// No compression, the cheapest option for already compressed media
+    #[default]
+    Store,
+    Deflate,
+}
+
+type PlaintextStream = Pin<Box<dyn Stream<Item = Result<Bytes, FilenSDKError>> + Send + Sync>>;
+
+/// A file or folder to be written into the archive, with its path inside the archive
+enum ZipSource {
+    Directory {
+        name: String,
+    },
+    File {
+        name: String,
+        file: Box<FilenFileDetailed>,
+    },
+}
+
+#[uniffi_async_export]
+impl FilenSDK {
+    /// Writes the ZIP archive of `uuids` to `output_file`, see `export_zip`. Returns the size of
+    /// the archive.
+    pub async fn export_zip_to_file(
+        &self,
+        uuids: Vec<String>,
+        compression: ZipCompression,
+        output_file: String,
+    ) -> Result<u64, FilenSDKError> {
+        let mut stream = std::pin::pin!(self.export_zip(uuids, compression).await?);
+        let mut output = tokio::fs::File::create(&output_file).await?;
+
+        let mut written = 0;
+        while let Some(data) = stream.try_next().await? {
+            output.write_all(&data).await?;
+            written += data.len() as u64;
+        }
+        output.flush().await?;
+
+        Ok(written)
+    }
+}
+
+impl FilenSDK {
+    /// Streams a ZIP archive of the given files and folders. Folders are added recursively under
+    /// their own name, and clashing names at the top level get a ` (n)` suffix.
+    ///
+    /// Entries are resolved before the stream is returned, after that file contents are
+    /// downloaded one file at a time through `read_ahead_download_stream`, so no file is ever
+    /// buffered as a whole. Sizes and checksums follow each file in a data descriptor, and ZIP64
+    /// records are used for large files and archives. Each file is checked against the hash in
+    /// its metadata, and a mismatch ends the stream with an `IntegrityError`.
+    pub async fn export_zip(
+        &self,
+        uuids: Vec<String>,
+        compression: ZipCompression,
+    ) -> Result<impl Stream<Item = Result<Bytes, FilenSDKError>> + Send + Sync + 'static, FilenSDKError>
+    {
+        let mut sources = Vec::new();
+        for source in self.zip_sources(uuids).await? {
+            let data = match &source {
+                ZipSource::File { file, .. } if file.size > 0 => {
+                    let stream = self.read_ahead_download_stream(ChunkSource::try_from(&**file)?, 0, file.size);
+                    let stream: PlaintextStream = Box::pin(verified_stream((**file).clone(), stream));
+                    Some(stream)
+                }
+                _ => None,
+            };
+            sources.push((source, data));
+        }
+
+        Ok(async_stream::try_stream! {
+            let mut zip = ZipWriter::new(compression);
+
+            for (source, data) in sources {
+                match source {
+                    ZipSource::Directory { name } => yield zip.add_directory(&name),
+                    ZipSource::File { name, file } => {
+                        yield zip.start_file(&name, file.last_modified, file.size);
+
+                        let mut received = 0;
+                        if let Some(mut data) = data {
+                            while let Some(chunk) = data.next().await {
+                                let chunk = chunk?;
+                                received += chunk.len() as u64;
+
+                                let output = zip.write(chunk)?;
+                                if !output.is_empty() {
+                                    yield output;
+                                }
+                            }
+                        }
+
+                        // The read-ahead stream ends early when a chunk fails to download
+                        if received != file.size {
+                            Err(FilenSDKError::DownloadError {
+                                err_str: format!(
+                                    "Received {} of {} bytes for {}",
+                                    received, file.size, name
+                                ),
+                            })?;
+                        }
+
+                        yield zip.finish_file()?;
+                    }
+                }
+            }
+
+            yield zip.finish();
+        })
+    }
+
+    async fn zip_sources(&self, uuids: Vec<String>) -> Result<Vec<ZipSource>, FilenSDKError> {
+        let mut sources = Vec::new();
+        let mut taken = HashSet::new();
+
+        for uuid in uuids {
+            match self.file_info(uuid.clone()).await {
+                Ok(file) => sources.push(ZipSource::File {
+                    name: unique_name(&file.name, &mut taken)?,
+                    file: Box::new(file),
+                }),
+                Err(err) if err.is_not_found() => {
+                    let root = unique_name(&self.dir_info(uuid.clone()).await?.name, &mut taken)?;
+                    sources.push(ZipSource::Directory {
+                        name: format!("{}/", root),
+                    });
+
+                    let entries: Vec<FilenTreeEntry> = self
+                        .walk_tree(uuid, FilenWalkOptions::default())
+                        .try_collect()
+                        .await?;
+                    for entry in entries {
+                        let name = format!("{}/{}", root, entry.relative_path);
+                        if !is_safe_path(&name) {
+                            return Err(FilenSDKError::InvalidPath { path: name });
+                        }
+
+                        sources.push(match entry.item {
+                            DecryptedStreamedDirContentResponse::Folders(_) => {
+                                ZipSource::Directory {
+                                    name: format!("{}/", name),
+                                }
+                            }
+                            DecryptedStreamedDirContentResponse::Uploads(file) => {
+                                ZipSource::File { name, file: Box::new(file) }
+                            }
+                        });
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(sources)
+    }
+}
+
+/// Picks a free top level name, compared case-insensitively so the archive extracts cleanly on
+/// case-insensitive filesystems.
+fn unique_name(name: &str, taken: &mut HashSet<String>) -> Result<String, FilenSDKError> {
+    if !is_safe_path(name) || name.contains('/') {
+        return Err(FilenSDKError::InvalidPath {
+            path: name.to_string(),
+        });
+    }
+
+    let mut candidate = name.to_string();
+    let mut n = 0;
+    while taken.contains(&candidate.to_lowercase()) {
+        n += 1;
+        candidate = suffixed_name(name, n);
+    }
+
+    taken.insert(candidate.to_lowercase());
+    Ok(candidate)
+}
+
+/// Rejects archive paths that could escape the extraction directory
+fn is_safe_path(path: &str) -> bool {
+    path.split('/')
+        .all(|segment| !matches!(segment, "" | "." | "..") && !segment.contains('\\'))
+}
+
+/// Converts a timestamp in milliseconds to MS-DOS `(time, date)`. Anything not representable
+/// becomes 1980-01-01 00:00.
+fn dos_date_time(last_modified: Option<i64>) -> (u16, u16) {
+    const DOS_EPOCH: (u16, u16) = (0, (1 << 5) | 1);
+
+    let Some(seconds) = last_modified.map(|ms| ms.div_euclid(1000)) else {
+        return DOS_EPOCH;
+    };
+
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let days = seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    if !(1980..=2107).contains(&year) {
+        return DOS_EPOCH;
+    }
+
+    let seconds_of_day = seconds.rem_euclid(86400);
+    let time = ((seconds_of_day / 3600) << 11) | ((seconds_of_day % 3600 / 60) << 5) | ((seconds_of_day % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+struct CentralEntry {
+    name: Vec<u8>,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    offset: u64,
+    external_attributes: u32,
+    zip64: bool,
+}
+
+struct OpenFile {
+    entry: CentralEntry,
+    hasher: crc32fast::Hasher,
+    encoder: Option<DeflateEncoder<Vec<u8>>>,
+}
+
+/// Produces a ZIP archive piece by piece without seeking, every method returns the bytes to
+/// append to the output.
+struct ZipWriter {
+    compression: ZipCompression,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    current: Option<OpenFile>,
+}
+
+impl ZipWriter {
+    fn new(compression: ZipCompression) -> Self {
+        Self {
+            compression,
+            offset: 0,
+            entries: Vec::new(),
+            current: None,
+        }
+    }
+
+    fn local_header(&mut self, entry: &CentralEntry) -> Bytes {
+        let mut header = BytesMut::with_capacity(30 + entry.name.len() + 20);
+        header.put_u32_le(LOCAL_FILE_HEADER);
+        header.put_u16_le(if entry.zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+        header.put_u16_le(entry.flags);
+        header.put_u16_le(entry.method);
+        header.put_u16_le(entry.time);
+        header.put_u16_le(entry.date);
+        // Checksum and sizes follow in the data descriptor
+        header.put_u32_le(0);
+        let size = if entry.zip64 { u32::MAX } else { 0 };
+        header.put_u32_le(size);
+        header.put_u32_le(size);
+        header.put_u16_le(entry.name.len() as u16);
+        header.put_u16_le(if entry.zip64 { 20 } else { 0 });
+        header.put_slice(&entry.name);
+        if entry.zip64 {
+            header.put_u16_le(ZIP64_EXTRA_FIELD);
+            header.put_u16_le(16);
+            header.put_u64_le(0);
+            header.put_u64_le(0);
+        }
+
+        self.offset += header.len() as u64;
+        header.freeze()
+    }
+
+    fn add_directory(&mut self, name: &str) -> Bytes {
+        let entry = CentralEntry {
+            name: name.as_bytes().to_vec(),
+            flags: FLAG_UTF8,
+            method: METHOD_STORE,
+            time: dos_date_time(None).0,
+            date: dos_date_time(None).1,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            offset: self.offset,
+            external_attributes: ATTRIBUTE_DIRECTORY,
+            zip64: false,
+        };
+
+        let header = self.local_header(&entry);
+        self.entries.push(entry);
+        header
+    }
+
+    fn start_file(&mut self, name: &str, last_modified: Option<i64>, size: u64) -> Bytes {
+        let (time, date) = dos_date_time(last_modified);
+        let deflate = self.compression == ZipCompression::Deflate && size > 0;
+
+        let entry = CentralEntry {
+            name: name.as_bytes().to_vec(),
+            flags: FLAG_UTF8 | FLAG_DATA_DESCRIPTOR,
+            method: if deflate { METHOD_DEFLATE } else { METHOD_STORE },
+            time,
+            date,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            offset: self.offset,
+            external_attributes: 0,
+            zip64: size >= ZIP64_THRESHOLD,
+        };
+
+        let header = self.local_header(&entry);
+        self.current = Some(OpenFile {
+            entry,
+            hasher: crc32fast::Hasher::new(),
+            encoder: deflate.then(|| DeflateEncoder::new(Vec::new(), flate2::Compression::fast())),
+        });
+        header
+    }
+
+    fn write(&mut self, data: Bytes) -> Result<Bytes, FilenSDKError> {
+        let file = self.current.as_mut().ok_or_else(no_open_file)?;
+        file.hasher.update(&data);
+        file.entry.uncompressed_size += data.len() as u64;
+
+        let output = match &mut file.encoder {
+            Some(encoder) => {
+                encoder.write_all(&data)?;
+                Bytes::from(std::mem::take(encoder.get_mut()))
+            }
+            None => data,
+        };
+
+        file.entry.compressed_size += output.len() as u64;
+        self.offset += output.len() as u64;
+        Ok(output)
+    }
+
+    fn finish_file(&mut self) -> Result<Bytes, FilenSDKError> {
+        let OpenFile {
+            mut entry,
+            hasher,
+            encoder,
+        } = self.current.take().ok_or_else(no_open_file)?;
+
+        let mut output = BytesMut::new();
+        if let Some(encoder) = encoder {
+            output.put_slice(&encoder.finish()?);
+            entry.compressed_size += output.len() as u64;
+        }
+        entry.crc32 = hasher.finalize();
+
+        output.put_u32_le(DATA_DESCRIPTOR);
+        output.put_u32_le(entry.crc32);
+        if entry.zip64 {
+            output.put_u64_le(entry.compressed_size);
+            output.put_u64_le(entry.uncompressed_size);
+        } else {
+            output.put_u32_le(entry.compressed_size as u32);
+            output.put_u32_le(entry.uncompressed_size as u32);
+        }
+
+        self.offset += output.len() as u64;
+        self.entries.push(entry);
+        Ok(output.freeze())
+    }
+
+    /// Writes the central directory and end records
+    fn finish(self) -> Bytes {
+        let mut output = BytesMut::new();
+        let directory_offset = self.offset;
+
+        for entry in &self.entries {
+            let zip64 = entry.zip64 || entry.offset >= u32::MAX as u64;
+
+            output.put_u32_le(CENTRAL_DIRECTORY_HEADER);
+            output.put_u16_le(VERSION_ZIP64);
+            output.put_u16_le(if zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+            output.put_u16_le(entry.flags);
+            output.put_u16_le(entry.method);
+            output.put_u16_le(entry.time);
+            output.put_u16_le(entry.date);
+            output.put_u32_le(entry.crc32);
+            if zip64 {
+                output.put_u32_le(u32::MAX);
+                output.put_u32_le(u32::MAX);
+            } else {
+                output.put_u32_le(entry.compressed_size as u32);
+                output.put_u32_le(entry.uncompressed_size as u32);
+            }
+            output.put_u16_le(entry.name.len() as u16);
+            output.put_u16_le(if zip64 { 28 } else { 0 });
+            // Comment length, disk number and internal attributes
+            output.put_u16_le(0);
+            output.put_u16_le(0);
+            output.put_u16_le(0);
+            output.put_u32_le(entry.external_attributes);
+            output.put_u32_le(if zip64 { u32::MAX } else { entry.offset as u32 });
+            output.put_slice(&entry.name);
+            if zip64 {
+                output.put_u16_le(ZIP64_EXTRA_FIELD);
+                output.put_u16_le(24);
+                output.put_u64_le(entry.uncompressed_size);
+                output.put_u64_le(entry.compressed_size);
+                output.put_u64_le(entry.offset);
+            }
+        }
+
+        let directory_size = output.len() as u64;
+        let entries = self.entries.len() as u64;
+
+        if entries >= u16::MAX as u64
+            || directory_offset >= u32::MAX as u64
+            || directory_size >= u32::MAX as u64
+        {
+            let zip64_end_offset = directory_offset + directory_size;
+
+            output.put_u32_le(ZIP64_END_OF_CENTRAL_DIRECTORY);
+            output.put_u64_le(44);
+            output.put_u16_le(VERSION_ZIP64);
+            output.put_u16_le(VERSION_ZIP64);
+            output.put_u32_le(0);
+            output.put_u32_le(0);
+            output.put_u64_le(entries);
+            output.put_u64_le(entries);
+            output.put_u64_le(directory_size);
+            output.put_u64_le(directory_offset);
+
+            output.put_u32_le(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR);
+            output.put_u32_le(0);
+            output.put_u64_le(zip64_end_offset);
+            output.put_u32_le(1);
+        }
+
+        output.put_u32_le(END_OF_CENTRAL_DIRECTORY);
+        output.put_u16_le(0);
+        output.put_u16_le(0);
+        output.put_u16_le(entries.min(u16::MAX as u64) as u16);
+        output.put_u16_le(entries.min(u16::MAX as u64) as u16);
+        output.put_u32_le(directory_size.min(u32::MAX as u64) as u32);
+        output.put_u32_le(directory_offset.min(u32::MAX as u64) as u32);
+        output.put_u16_le(0);
+
+        output.freeze()
+    }
+}
+
+fn no_open_file() -> FilenSDKError {
+    FilenSDKError::UnknownError {
+        err_str: "No open ZIP entry".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn build_zip(compression: ZipCompression, files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(compression);
+        let mut output = Vec::new();
+
+        output.extend_from_slice(&zip.add_directory("folder/"));
+        for (name, data) in files {
+            output.extend_from_slice(&zip.start_file(name, Some(1_700_000_000_000), data.len() as u64));
+            // Feed the data in pieces, like chunks of a download
+            for piece in data.chunks(1000) {
+                output.extend_from_slice(&zip.write(Bytes::copy_from_slice(piece)).unwrap());
+            }
+            output.extend_from_slice(&zip.finish_file().unwrap());
+        }
+        output.extend_from_slice(&zip.finish());
+        output
+    }
+
+    #[test]
+    fn test_zip_round_trip() {
+        let text = "hello zip ".repeat(1000);
+        let files: [(&str, &[u8]); 3] = [
+            ("folder/a.txt", text.as_bytes()),
+            ("folder/empty.txt", b""),
+            ("b.bin", &[0, 1, 2, 3, 255]),
+        ];
+
+        for compression in [ZipCompression::Store, ZipCompression::Deflate] {
+            let archive = build_zip(compression, &files);
+            let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+            assert_eq!(archive.len(), 4);
+            assert!(archive.by_name("folder/").unwrap().is_dir());
+            for (name, data) in files {
+                let mut file = archive.by_name(name).unwrap();
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_zip64_records() {
+        let mut zip = ZipWriter::new(ZipCompression::Store);
+        let mut output = Vec::new();
+
+        // Announced as large, which switches the entry to ZIP64 sizes and offsets
+        output.extend_from_slice(&zip.start_file("big.bin", None, ZIP64_THRESHOLD));
+        output.extend_from_slice(&zip.write(Bytes::from_static(b"data")).unwrap());
+        output.extend_from_slice(&zip.finish_file().unwrap());
+        output.extend_from_slice(&zip.finish());
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(output)).unwrap();
+        let mut contents = Vec::new();
+        archive.by_name("big.bin").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"data");
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2023-11-14 22:13:20 UTC
+        let (time, date) = dos_date_time(Some(1_700_000_000_000));
+        assert_eq!((time >> 11, (time >> 5) & 0x3f, (time & 0x1f) * 2), (22, 13, 20));
+        assert_eq!(((date >> 9) + 1980, (date >> 5) & 0xf, date & 0x1f), (2023, 11, 14));
+
+        assert_eq!(dos_date_time(Some(0)), (0, (1 << 5) | 1));
+        assert_eq!(dos_date_time(None), (0, (1 << 5) | 1));
+
+        // The same time as stored by the upload path
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_000);
+        let uploaded = crate::httpclient::fs_upload::tests::uploaded_file(modified);
+        assert_eq!(dos_date_time(uploaded.metadata().last_modified), (time, date));
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_name("a.txt", &mut taken).unwrap(), "a.txt");
+        assert_eq!(unique_name("A.txt", &mut taken).unwrap(), "A (1).txt");
+        assert_eq!(unique_name("a.txt", &mut taken).unwrap(), "a (2).txt");
+        assert!(unique_name("..", &mut taken).is_err());
+        assert!(unique_name("a/b", &mut taken).is_err());
+    }
+}
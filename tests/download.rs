@@ -206,7 +206,7 @@ mod tests {
             ctx.key.clone(),
         );
 
-        while let Ok(chunk) = stream.next_blocking() {
+        while let Some(chunk) = stream.next_blocking().unwrap() {
             let file_path = format!("{}/{}", ctx.output_dir, ctx.file_name);
             let mut file = std::fs::OpenOptions::new()
                 .append(true)
@@ -21,7 +21,7 @@ mod tests {
             .upload_file_low_memory_blocking(input_file.to_string(), filen_parent, name, "tests/tmp/test_up".to_string(), true);
         assert!(result.is_ok());
 
-        let uuid = result.unwrap();
+        let uuid = result.unwrap().file.uuid;
 
         // Download file
         let download_path = "tests/out/test_download_out";